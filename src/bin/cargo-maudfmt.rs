@@ -0,0 +1,231 @@
+use std::{
+    collections::BTreeSet,
+    env,
+    ffi::OsString,
+    fs,
+    io::Write as _,
+    path::{Path, PathBuf},
+    process::{self, Command, Stdio},
+};
+
+use anyhow::{Context, Result, bail};
+use cargo_metadata::{Metadata, MetadataCommand, Package, TargetKind};
+use clap::{Args, Parser};
+use syn::{Attribute, Expr, ExprLit, Item, Lit, Meta, ext::IdentExt as _};
+
+#[derive(Parser)]
+#[command(bin_name = "cargo")]
+enum CargoCli {
+    /// Format the maud macros of the current crate
+    Maudfmt(MaudfmtArgs),
+}
+
+#[derive(Args)]
+#[command(version, about)]
+struct MaudfmtArgs {
+    /// Format all packages of the workspace
+    #[arg(long, default_value = "false")]
+    all: bool,
+
+    /// Package to format (can be repeated)
+    #[arg(short, long, value_name = "PACKAGE")]
+    package: Vec<String>,
+
+    /// Don't write anything, exit with an error if some files are not formatted
+    #[arg(long, default_value = "false")]
+    check: bool,
+
+    /// Path to Cargo.toml
+    #[arg(long, value_name = "PATH")]
+    manifest_path: Option<PathBuf>,
+
+    /// Options passed to maudfmt, like `--line-length 80`
+    #[arg(last = true, value_name = "MAUDFMT_ARGS")]
+    maudfmt_args: Vec<OsString>,
+}
+
+fn main() -> Result<()> {
+    let CargoCli::Maudfmt(args) = CargoCli::parse();
+
+    let mut metadata_command = MetadataCommand::new();
+    metadata_command.no_deps();
+    if let Some(manifest_path) = &args.manifest_path {
+        metadata_command.manifest_path(manifest_path);
+    }
+    let metadata = metadata_command
+        .exec()
+        .context("Failed to run `cargo metadata`")?;
+
+    let mut files = BTreeSet::new();
+    for package in selected_packages(&metadata, &args)? {
+        src_files(package, &mut files);
+    }
+    if files.is_empty() {
+        return Ok(());
+    }
+
+    // same pipeline as `maudfmt --files-from`, with the files of the selected targets
+    let mut child = Command::new(maudfmt_path())
+        .args(args.check.then_some("--check"))
+        .args(&args.maudfmt_args)
+        .args(["--files-from", "-", "-0"])
+        .stdin(Stdio::piped())
+        .spawn()
+        .context("Failed to run maudfmt")?;
+    let mut stdin = child
+        .stdin
+        .take()
+        .context("Failed to open maudfmt's stdin")?;
+    for file in &files {
+        write!(stdin, "{}\0", file.display()).context("Failed to write to maudfmt's stdin")?;
+    }
+    drop(stdin);
+    let status = child.wait().context("Failed to run maudfmt")?;
+
+    process::exit(status.code().unwrap_or(1));
+}
+
+fn selected_packages<'a>(metadata: &'a Metadata, args: &MaudfmtArgs) -> Result<Vec<&'a Package>> {
+    let members = metadata.workspace_packages();
+
+    if args.all {
+        return Ok(members);
+    }
+    if !args.package.is_empty() {
+        return args
+            .package
+            .iter()
+            .map(|name| {
+                members
+                    .iter()
+                    .find(|package| package.name.as_str() == name)
+                    .copied()
+                    .with_context(|| format!("Package `{name}` is not a member of the workspace"))
+            })
+            .collect();
+    }
+
+    // like `cargo fmt`: the current package, or every member of a virtual workspace
+    let manifest_path = match &args.manifest_path {
+        Some(manifest_path) => Some(manifest_path.canonicalize()?),
+        None => env::current_dir()?
+            .ancestors()
+            .map(|dir| dir.join("Cargo.toml"))
+            .find(|manifest_path| manifest_path.is_file()),
+    };
+    let current_package = members
+        .iter()
+        .copied()
+        .find(|package| package.manifest_path.canonicalize().ok() == manifest_path);
+    match current_package {
+        Some(package) => Ok(vec![package]),
+        None if members.is_empty() => bail!("No package found"),
+        None => Ok(members),
+    }
+}
+
+/// Rust files of every target of `package`: its root file and the modules it declares
+fn src_files(package: &Package, files: &mut BTreeSet<PathBuf>) {
+    let targets = package.targets.iter().filter(|target| {
+        target.kind.iter().any(|kind| {
+            matches!(
+                kind,
+                TargetKind::Lib
+                    | TargetKind::RLib
+                    | TargetKind::DyLib
+                    | TargetKind::CDyLib
+                    | TargetKind::StaticLib
+                    | TargetKind::ProcMacro
+                    | TargetKind::Bin
+                    | TargetKind::Test
+                    | TargetKind::Example
+                    | TargetKind::Bench
+                    | TargetKind::CustomBuild
+            )
+        })
+    });
+    for target in targets {
+        module_files(target.src_path.as_std_path(), true, files);
+    }
+}
+
+/// Add `file` and the files of the modules it declares. The submodules of a crate root or of a
+/// `mod.rs` file are in its directory, the ones of `foo.rs` in `foo/`.
+fn module_files(file: &Path, mod_rs: bool, files: &mut BTreeSet<PathBuf>) {
+    if !file.is_file() || !files.insert(file.to_path_buf()) {
+        return;
+    }
+    // maudfmt reports the files it can't parse
+    let Some(ast) = fs::read_to_string(file)
+        .ok()
+        .and_then(|source| syn::parse_file(&source).ok())
+    else {
+        return;
+    };
+
+    let file_dir = file.parent().unwrap_or(Path::new(""));
+    let mod_dir = match file.file_stem() {
+        Some(stem) if !mod_rs && file.file_name() != Some("mod.rs".as_ref()) => file_dir.join(stem),
+        _ => file_dir.to_path_buf(),
+    };
+    declared_modules(&ast.items, file_dir, &mod_dir, files);
+}
+
+/// `path_dir` is the directory `#[path]` attributes are relative to, `mod_dir` the one of the
+/// submodules
+fn declared_modules(
+    items: &[Item],
+    path_dir: &Path,
+    mod_dir: &Path,
+    files: &mut BTreeSet<PathBuf>,
+) {
+    for item in items {
+        let Item::Mod(item_mod) = item else {
+            continue;
+        };
+        let name = item_mod.ident.unraw().to_string();
+        let path = path_attr(&item_mod.attrs);
+        match (&item_mod.content, path) {
+            (Some((_, items)), path) => {
+                let dir = mod_dir.join(path.unwrap_or(name));
+                declared_modules(items, &dir, &dir, files);
+            }
+            // like `mod.rs` files, the submodules of a `#[path]` file are in its directory
+            (None, Some(path)) => module_files(&path_dir.join(path), true, files),
+            (None, None) => {
+                let file = mod_dir.join(format!("{name}.rs"));
+                if file.is_file() {
+                    module_files(&file, false, files);
+                } else {
+                    module_files(&mod_dir.join(&name).join("mod.rs"), true, files);
+                }
+            }
+        }
+    }
+}
+
+/// Value of the `#[path = "..."]` attribute of a module
+fn path_attr(attrs: &[Attribute]) -> Option<String> {
+    attrs.iter().find_map(|attr| match &attr.meta {
+        Meta::NameValue(name_value) if name_value.path.is_ident("path") => {
+            match &name_value.value {
+                Expr::Lit(ExprLit {
+                    lit: Lit::Str(path),
+                    ..
+                }) => Some(path.value()),
+                _ => None,
+            }
+        }
+        _ => None,
+    })
+}
+
+/// `maudfmt` installed next to this executable, like `cargo fmt` does with rustfmt
+fn maudfmt_path() -> PathBuf {
+    let name = format!("maudfmt{}", env::consts::EXE_SUFFIX);
+    env::current_exe()
+        .ok()
+        .map(|exe| exe.with_file_name(&name))
+        .filter(|path| Path::exists(path))
+        .unwrap_or_else(|| PathBuf::from(name))
+}
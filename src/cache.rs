@@ -0,0 +1,106 @@
+use std::{
+    env, fs,
+    hash::Hash as _,
+    io,
+    path::{Path, PathBuf},
+};
+
+use maudfmt::FormatOptions;
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::InputMode;
+
+/// Remembers the content of the files already formatted, so that they can be skipped
+pub struct Cache {
+    dir: PathBuf,
+    options: FormatOptions,
+}
+
+impl Cache {
+    pub fn new(dir: PathBuf, options: &FormatOptions) -> Self {
+        Cache {
+            dir,
+            options: options.clone(),
+        }
+    }
+
+    /// `maudfmt` directory inside the target directory of the closest cargo project
+    pub fn default_dir() -> Option<PathBuf> {
+        if let Some(target_dir) = env::var_os("CARGO_TARGET_DIR") {
+            return Some(PathBuf::from(target_dir).join("maudfmt"));
+        }
+
+        let current_dir = env::current_dir().ok()?;
+        let manifest_dirs: Vec<&Path> = current_dir
+            .ancestors()
+            .filter(|dir| dir.join("Cargo.toml").is_file())
+            .collect();
+        // the target directory of a workspace member is the one of the workspace
+        let project_dir = manifest_dirs
+            .iter()
+            .find(|dir| {
+                fs::read_to_string(dir.join("Cargo.toml"))
+                    .is_ok_and(|manifest| manifest.lines().any(|line| line.trim() == "[workspace]"))
+            })
+            .or(manifest_dirs.first())?;
+
+        Some(project_dir.join("target").join("maudfmt"))
+    }
+
+    /// Returns true if `source` is known to be formatted
+    pub fn is_formatted(&self, source: &str, mode: InputMode) -> bool {
+        self.entry_path(source, mode).is_file()
+    }
+
+    pub fn set_formatted(&self, source: &str, mode: InputMode) -> io::Result<()> {
+        let entry_path = self.entry_path(source, mode);
+        if let Some(parent) = entry_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(entry_path, "")
+    }
+
+    // one empty file per formatted content, so that concurrent runs can share the cache
+    fn entry_path(&self, source: &str, mode: InputMode) -> PathBuf {
+        let mut hasher = Xxh3::new();
+        env!("CARGO_PKG_VERSION").hash(&mut hasher);
+        self.options.hash(&mut hasher);
+        mode.hash(&mut hasher);
+        source.hash(&mut hasher);
+        let key = format!("{:032x}", hasher.digest128());
+
+        self.dir.join(&key[..2]).join(&key[2..])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cache_key() {
+        let dir = env::temp_dir().join(format!("maudfmt-cache-test-{}", std::process::id()));
+        let options = FormatOptions::default();
+        let cache = Cache::new(dir.clone(), &options);
+
+        assert!(!cache.is_formatted("fn main() {}\n", InputMode::Rust));
+        cache
+            .set_formatted("fn main() {}\n", InputMode::Rust)
+            .unwrap();
+        assert!(cache.is_formatted("fn main() {}\n", InputMode::Rust));
+
+        // content, mode and options are part of the key
+        assert!(!cache.is_formatted("fn main() { }\n", InputMode::Rust));
+        assert!(!cache.is_formatted("fn main() {}\n", InputMode::Markdown));
+        let other_options = FormatOptions {
+            line_length: 80,
+            ..FormatOptions::default()
+        };
+        assert!(
+            !Cache::new(dir.clone(), &other_options)
+                .is_formatted("fn main() {}\n", InputMode::Rust)
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,14 +1,22 @@
 use crop::Rope;
+use proc_macro2::LineColumn;
+use quote::ToTokens as _;
 use syn::{
-    File, Macro, Meta,
+    File, Macro, Meta, MetaNameValue, Path, Token,
+    punctuated::Punctuated,
     spanned::Spanned,
     visit::{self, Visit},
 };
 
+use crate::format::FormatOverrides;
+
+const OVERRIDES_PREFIX: &str = "maudfmt:";
+
 pub struct MaudMacro<'a> {
     pub macro_: &'a Macro,
     pub indent: Indent,
     pub macro_name: String,
+    pub overrides: FormatOverrides,
 }
 
 pub struct Indent {
@@ -16,11 +24,20 @@ pub struct Indent {
     pub spaces: usize,
 }
 
+/// A `key=value` override that is not a known option with a valid value, ignored
+pub struct InvalidOverride {
+    /// Start of the comment or attribute
+    pub loc: LineColumn,
+    pub setting: String,
+}
+
 struct MacroVisitor<'a> {
     macros: Vec<MaudMacro<'a>>,
     source: Rope,
     macro_names: &'a Vec<String>,
     skip_count: usize,
+    overrides: FormatOverrides,
+    invalid_overrides: Vec<InvalidOverride>,
 }
 
 impl<'ast> Visit<'ast> for MacroVisitor<'ast> {
@@ -42,10 +59,13 @@ impl<'ast> Visit<'ast> for MacroVisitor<'ast> {
             let tabs = indent_chars.iter().filter(|&&c| c == '\t').count();
             let spaces = indent_chars.iter().filter(|&&c| c == ' ').count();
 
+            let comment_overrides = self.comment_overrides(span_line);
+            let overrides = self.overrides.merge(&comment_overrides);
             self.macros.push(MaudMacro {
                 macro_: node,
                 indent: Indent { tabs, spaces },
                 macro_name: get_macro_full_path(node),
+                overrides,
             })
         }
 
@@ -57,14 +77,18 @@ impl<'ast> Visit<'ast> for MacroVisitor<'ast> {
     // this means we save the skipped length and set it back to its original length
     fn visit_stmt(&mut self, i: &'ast syn::Stmt) {
         let skipped_len = self.skip_count;
+        let overrides = self.overrides.clone();
         syn::visit::visit_stmt(self, i);
         self.skip_count = skipped_len;
+        self.overrides = overrides;
     }
 
     fn visit_item(&mut self, i: &'ast syn::Item) {
         let skipped_len = self.skip_count;
+        let overrides = self.overrides.clone();
         syn::visit::visit_item(self, i);
         self.skip_count = skipped_len;
+        self.overrides = overrides;
     }
 
     fn visit_attribute(&mut self, i: &'ast syn::Attribute) {
@@ -72,11 +96,133 @@ impl<'ast> Visit<'ast> for MacroVisitor<'ast> {
         if attr_is_rustfmt_skip(i) {
             self.skip_count += 1;
         }
+        let mut invalid = Vec::new();
+        if let Some(overrides) = meta_overrides(&i.meta, &mut invalid) {
+            self.overrides = self.overrides.merge(&overrides);
+        }
+        self.invalid_overrides
+            .extend(invalid.into_iter().map(|setting| InvalidOverride {
+                loc: i.span().start(),
+                setting,
+            }));
 
         syn::visit::visit_attribute(self, i);
     }
 }
 
+impl MacroVisitor<'_> {
+    /// Overrides from `// maudfmt: key=value` comments on the lines right above the macro
+    fn comment_overrides(&mut self, span_line: usize) -> FormatOverrides {
+        let mut overrides = FormatOverrides::default();
+        let mut line_idx = span_line - 1;
+
+        while line_idx > 0 {
+            let line = self.source.line(line_idx - 1).to_string();
+            let Some(comment) = line.trim().strip_prefix("//") else {
+                break;
+            };
+            let mut invalid = Vec::new();
+            if let Some(comment_overrides) = parse_overrides_comment(comment, &mut invalid) {
+                // the closest comment wins
+                overrides = comment_overrides.merge(&overrides);
+            }
+            let column = line.find("//").unwrap_or_default();
+            self.invalid_overrides
+                .extend(invalid.into_iter().map(|setting| InvalidOverride {
+                    loc: LineColumn {
+                        line: line_idx,
+                        column,
+                    },
+                    setting,
+                }));
+            line_idx -= 1;
+        }
+
+        overrides
+    }
+}
+
+/// Parse `maudfmt: line_length=140, single_line_macros=true`, the invalid settings are added to
+/// `invalid`
+fn parse_overrides_comment(comment: &str, invalid: &mut Vec<String>) -> Option<FormatOverrides> {
+    let settings = comment.trim().strip_prefix(OVERRIDES_PREFIX)?;
+    if !settings.contains('=') {
+        // layout directives (`// maudfmt: expand`) have no value
+        return None;
+    }
+
+    let mut overrides = FormatOverrides::default();
+    for setting in settings.split(',') {
+        match setting.split_once('=') {
+            Some((key, value)) => set_override(&mut overrides, key.trim(), value.trim(), invalid),
+            None => set_override(&mut overrides, setting.trim(), "", invalid),
+        }
+    }
+    Some(overrides)
+}
+
+/// Parse `maudfmt::options(line_length = 140)`, also when wrapped in a `cfg_attr`, the invalid
+/// settings are added to `invalid`
+fn meta_overrides(meta: &Meta, invalid: &mut Vec<String>) -> Option<FormatOverrides> {
+    let Meta::List(list) = meta else {
+        return None;
+    };
+
+    match path_to_string(&list.path).as_str() {
+        "cfg_attr" => list
+            .parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)
+            .ok()?
+            .iter()
+            // skip the cfg predicate
+            .skip(1)
+            .filter_map(|meta| meta_overrides(meta, invalid))
+            .reduce(|acc, overrides| acc.merge(&overrides)),
+        "maudfmt::options" => {
+            let mut overrides = FormatOverrides::default();
+            for setting in list
+                .parse_args_with(Punctuated::<MetaNameValue, Token![,]>::parse_terminated)
+                .ok()?
+            {
+                set_override(
+                    &mut overrides,
+                    &path_to_string(&setting.path),
+                    &setting.value.to_token_stream().to_string(),
+                    invalid,
+                );
+            }
+            Some(overrides)
+        }
+        _ => None,
+    }
+}
+
+fn set_override(
+    overrides: &mut FormatOverrides,
+    key: &str,
+    value: &str,
+    invalid: &mut Vec<String>,
+) {
+    let is_valid = match key {
+        "line_length" => value
+            .parse()
+            .map(|v| overrides.line_length = Some(v))
+            .is_ok(),
+        "single_line_macros" => value
+            .parse()
+            .map(|v| overrides.single_line_macros = Some(v))
+            .is_ok(),
+        "strip_layout_directives" => value
+            .parse()
+            .map(|v| overrides.strip_layout_directives = Some(v))
+            .is_ok(),
+        _ => false,
+    };
+
+    if !is_valid {
+        invalid.push(format!("{key}={value}"));
+    }
+}
+
 /// Check if an attribute is a rustfmt skip attribute
 fn attr_is_rustfmt_skip(i: &syn::Attribute) -> bool {
     match &i.meta {
@@ -91,34 +237,85 @@ fn attr_is_rustfmt_skip(i: &syn::Attribute) -> bool {
 }
 
 fn get_macro_full_path(mac: &Macro) -> String {
-    mac.path
-        .segments
+    path_to_string(&mac.path)
+}
+
+fn path_to_string(path: &Path) -> String {
+    path.segments
         .iter()
         .map(|path| path.ident.to_string())
         .collect::<Vec<String>>()
         .join("::")
 }
 
+/// The maud macros of `file`, and the overrides ignored because they are invalid
 pub fn collect_macros_from_file<'a>(
     file: &'a File,
     source: Rope,
     macro_names: &'a Vec<String>,
-) -> (Rope, Vec<MaudMacro<'a>>) {
+) -> (Rope, Vec<MaudMacro<'a>>, Vec<InvalidOverride>) {
     let mut macro_visitor = MacroVisitor {
         macros: Vec::new(),
         source,
         macro_names,
         skip_count: 0,
+        overrides: FormatOverrides::default(),
+        invalid_overrides: Vec::new(),
     };
     macro_visitor.visit_file(file);
 
-    (macro_visitor.source, macro_visitor.macros)
+    (
+        macro_visitor.source,
+        macro_visitor.macros,
+        macro_visitor.invalid_overrides,
+    )
 }
 
 #[cfg(test)]
 mod test {
+    use crop::Rope;
+
+    use super::*;
     use crate::testing::*;
 
+    #[test]
+    fn invalid_overrides_located() {
+        let source = r#"
+fn render() -> Markup {
+    // maudfmt: line_length=wide, single_line_macros=true
+    html! { p {} }
+}
+
+#[maudfmt::options(strip_layout_directives = maybe)]
+fn render_other() -> Markup {
+    html! { p {} }
+}
+"#;
+        let file = syn::parse_file(source).unwrap();
+        let macro_names = vec![String::from("html")];
+        let (_, macros, invalid) =
+            collect_macros_from_file(&file, Rope::from(source), &macro_names);
+
+        assert_eq!(macros[0].overrides.single_line_macros, Some(true));
+        let invalid: Vec<_> = invalid
+            .iter()
+            .map(|invalid| {
+                (
+                    invalid.loc.line,
+                    invalid.loc.column,
+                    invalid.setting.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            invalid,
+            [
+                (3, 4, "line_length=wide"),
+                (7, 0, "strip_layout_directives=maybe")
+            ]
+        );
+    }
+
     test_default!(
         rustfmt_skip,
         r#"
@@ -178,4 +375,99 @@ mod test {
         html! {p{}}
         "#
     );
+
+    test_default!(
+        comment_overrides,
+        r#"
+        // maudfmt: line_length=40
+        html! { div class="some-long-class-name" { "content" } }
+
+        html! { div class="some-long-class-name" { "content" } }
+        "#,
+        r#"
+        // maudfmt: line_length=40
+        html! {
+            div
+                class="some-long-class-name"
+            { "content" }
+        }
+
+        html! {
+            div class="some-long-class-name" { "content" }
+        }
+        "#
+    );
+
+    test_default!(
+        comment_overrides_multiple_settings,
+        r#"
+        fn render() -> Markup {
+            // maudfmt: line_length=60, single_line_macros=true
+            let x = html! {
+                "Hello " (name)
+            };
+        }
+        "#,
+        r#"
+        fn render() -> Markup {
+            // maudfmt: line_length=60, single_line_macros=true
+            let x = html! { "Hello " (name) };
+        }
+        "#
+    );
+
+    test_default!(
+        attribute_overrides,
+        r#"
+        #[maudfmt::options(line_length = 40)]
+        fn render() -> Markup {
+            html! { div class="some-long-class-name" { "content" } }
+        }
+
+        fn render_other() -> Markup {
+            html! { div class="some-long-class-name" { "content" } }
+        }
+        "#,
+        r#"
+        #[maudfmt::options(line_length = 40)]
+        fn render() -> Markup {
+            html! {
+                div
+                    class="some-long-class-name"
+                { "content" }
+            }
+        }
+
+        fn render_other() -> Markup {
+            html! {
+                div class="some-long-class-name" { "content" }
+            }
+        }
+        "#
+    );
+
+    test_default!(
+        cfg_attr_overrides,
+        r#"
+        fn render() -> Markup {
+            #[cfg_attr(any(), maudfmt::options(single_line_macros = true))]
+            let x = html! {
+                "Hello " (name)
+            };
+            let y = html! {
+                "Hello " (name)
+            };
+        }
+        "#,
+        r#"
+        fn render() -> Markup {
+            #[cfg_attr(any(), maudfmt::options(single_line_macros = true))]
+            let x = html! { "Hello " (name) };
+            let y = html! {
+                "Hello "
+                (name)
+            };
+        }
+        "#
+    );
 }
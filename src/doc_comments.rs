@@ -0,0 +1,279 @@
+use syn::{
+    Attribute, Expr, ExprLit, File, Lit, Meta,
+    spanned::Spanned as _,
+    visit::{self, Visit},
+};
+
+use crate::{
+    format::FormatOptions,
+    line_length::display_width,
+    markdown::{Flavor, format_markdown},
+};
+
+/// A `///` or `//!` doc comment line
+struct DocLine {
+    /// 0-based line index in the source
+    line_idx: usize,
+    /// indentation followed by `///` or `//!`
+    prefix: String,
+    /// comment text, without the prefix
+    text: String,
+}
+
+struct DocVisitor<'a> {
+    source_lines: &'a [&'a str],
+    doc_lines: Vec<DocLine>,
+}
+
+impl<'ast> Visit<'ast> for DocVisitor<'_> {
+    fn visit_attribute(&mut self, attr: &'ast Attribute) {
+        if let Some(doc_line) = self.doc_line(attr) {
+            self.doc_lines.push(doc_line);
+        }
+
+        visit::visit_attribute(self, attr);
+    }
+}
+
+impl DocVisitor<'_> {
+    fn doc_line(&self, attr: &Attribute) -> Option<DocLine> {
+        let Meta::NameValue(name_value) = &attr.meta else {
+            return None;
+        };
+        if !name_value.path.is_ident("doc") {
+            return None;
+        }
+        let Expr::Lit(ExprLit {
+            lit: Lit::Str(text),
+            ..
+        }) = &name_value.value
+        else {
+            return None;
+        };
+
+        // only sugared line comments, not `#[doc = "..."]` or `/** */`
+        let start = attr.span().start();
+        let line = self.source_lines.get(start.line - 1)?;
+        let (indent, comment) = line.split_at(line.char_indices().nth(start.column)?.0);
+        let marker = ["///", "//!"]
+            .into_iter()
+            .find(|marker| comment.starts_with(marker))?;
+        if !indent.trim().is_empty() {
+            return None;
+        }
+
+        Some(DocLine {
+            line_idx: start.line - 1,
+            prefix: format!("{indent}{marker}"),
+            text: text.value(),
+        })
+    }
+}
+
+/// Format the maud macros of the rust code blocks inside `///` and `//!` doc comments
+pub fn format_doc_comments(source: &str, file: &File, options: &FormatOptions) -> String {
+    let source_lines: Vec<&str> = source.split_inclusive('\n').collect();
+    let mut visitor = DocVisitor {
+        source_lines: &source_lines,
+        doc_lines: Vec::new(),
+    };
+    visitor.visit_file(file);
+
+    let mut doc_lines = visitor.doc_lines;
+    doc_lines.sort_by_key(|doc_line| doc_line.line_idx);
+
+    let mut output = String::with_capacity(source.len());
+    let mut next_line_idx = 0;
+    for comment in group_comments(&doc_lines) {
+        let first_line_idx = comment[0].line_idx;
+        let last_line_idx = comment[comment.len() - 1].line_idx;
+
+        for line in &source_lines[next_line_idx..first_line_idx] {
+            output.push_str(line);
+        }
+        match format_comment(comment, options) {
+            Some(formatted) => output.push_str(&formatted),
+            None => {
+                for line in &source_lines[first_line_idx..=last_line_idx] {
+                    output.push_str(line);
+                }
+            }
+        }
+        next_line_idx = last_line_idx + 1;
+    }
+    for line in &source_lines[next_line_idx..] {
+        output.push_str(line);
+    }
+
+    output
+}
+
+// consecutive doc lines with the same prefix form a single comment
+fn group_comments(doc_lines: &[DocLine]) -> Vec<&[DocLine]> {
+    let mut comments = Vec::new();
+    let mut start = 0;
+    for idx in 1..=doc_lines.len() {
+        let is_end = idx == doc_lines.len()
+            || doc_lines[idx].line_idx != doc_lines[idx - 1].line_idx + 1
+            || doc_lines[idx].prefix != doc_lines[idx - 1].prefix;
+        if is_end {
+            comments.push(&doc_lines[start..idx]);
+            start = idx;
+        }
+    }
+    comments
+}
+
+// returns None when nothing changed
+fn format_comment(comment: &[DocLine], options: &FormatOptions) -> Option<String> {
+    // `/// text` is the usual style, the space is not part of the markdown
+    let leading_space = comment
+        .iter()
+        .all(|doc_line| doc_line.text.is_empty() || doc_line.text.starts_with(' '));
+    let markdown: String = comment
+        .iter()
+        .map(|doc_line| {
+            let text = if leading_space {
+                doc_line.text.strip_prefix(' ').unwrap_or(&doc_line.text)
+            } else {
+                &doc_line.text
+            };
+            format!("{text}\n")
+        })
+        .collect();
+
+    // the examples are formatted within what the indentation and `/// ` leave of the line
+    let prefix = &comment[0].prefix;
+    let options = &FormatOptions {
+        line_length: options
+            .line_length
+            .saturating_sub(display_width(prefix) + usize::from(leading_space)),
+        ..options.clone()
+    };
+    let formatted = format_markdown(&markdown, options, Flavor::Rustdoc);
+    if formatted == markdown {
+        return None;
+    }
+
+    Some(
+        formatted
+            .lines()
+            .map(|line| match (line.is_empty(), leading_space) {
+                (true, _) => format!("{prefix}\n"),
+                (false, true) => format!("{prefix} {line}\n"),
+                (false, false) => format!("{prefix}{line}\n"),
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use crate::testing::*;
+
+    test_doc_comments!(
+        doc_comment_example,
+        r#"
+        /// A static footer.
+        ///
+        /// ```
+        /// # use maud::html;
+        /// let footer = html!{footer{a href="rss.atom"{"RSS Feed"}}};
+        /// ```
+        fn footer() -> Markup {
+            html!{footer{a href="rss.atom"{"RSS Feed"}}}
+        }
+        "#,
+        r#"
+        /// A static footer.
+        ///
+        /// ```
+        /// # use maud::html;
+        /// let footer = html! {
+        ///     footer {
+        ///         a href="rss.atom" { "RSS Feed" }
+        ///     }
+        /// };
+        /// ```
+        fn footer() -> Markup {
+            html! {
+                footer {
+                    a href="rss.atom" { "RSS Feed" }
+                }
+            }
+        }
+        "#
+    );
+
+    test_doc_comments!(
+        doc_comment_inner,
+        r#"
+//! Components
+//!
+//! ```rust,no_run
+//! html!{p{"Hello"}}
+//! ```
+//!
+//! ```text
+//! html!{p{"Hello"}}
+//! ```
+"#,
+        r#"
+//! Components
+//!
+//! ```rust,no_run
+//! html! {
+//!     p { "Hello" }
+//! }
+//! ```
+//!
+//! ```text
+//! html!{p{"Hello"}}
+//! ```
+"#
+    );
+
+    test_default!(
+        doc_comment_disabled_by_default,
+        r#"
+        /// ```
+        /// html!{p{"Hello"}}
+        /// ```
+        fn hello() {}
+        "#,
+        r#"
+        /// ```
+        /// html!{p{"Hello"}}
+        /// ```
+        fn hello() {}
+        "#
+    );
+
+    test_doc_comments!(
+        doc_comment_example_within_line_length,
+        r#"
+        impl Page {
+            /// ```
+            /// let page = html! {
+            ///     p { "Some text long enough to reach the limit" (name) "and a bit more text here" }
+            /// };
+            /// ```
+            fn page() {}
+        }
+        "#,
+        r#"
+        impl Page {
+            /// ```
+            /// let page = html! {
+            ///     p {
+            ///         "Some text long enough to reach the limit"
+            ///         (name)
+            ///         "and a bit more text here"
+            ///     }
+            /// };
+            /// ```
+            fn page() {}
+        }
+        "#
+    );
+}
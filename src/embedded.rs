@@ -0,0 +1,515 @@
+//! Formatters of the rust code embedded in the markup: splices, conditions, patterns and `@let`.
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    ffi::OsString,
+    mem, panic,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use proc_macro2::{TokenStream, TokenTree};
+use quote::quote;
+use syn::{Expr, Pat, Stmt};
+
+use crate::{
+    format::{FormatOptions, RustFormatterKind},
+    rustfmt,
+    unparse::{unparse_expr, unparse_pat, unparse_stmts},
+};
+
+const INDENT: &str = "    ";
+
+/// Where the formatted code goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Shape {
+    /// The lines are indented like the body of a block at this level
+    pub indent: usize,
+    /// Maximum line length
+    pub width: usize,
+}
+
+/// Rust code as written in the source
+#[derive(Debug, Default)]
+pub struct Original {
+    /// The lines after the first one are dedented by the indentation of the first one
+    pub lines: Vec<String>,
+    /// Indices of the lines starting inside a multi-line literal, kept as they are
+    pub literal_lines: HashSet<usize>,
+}
+
+impl Original {
+    /// `text` of `tokens`, starting on `start_line` (1-based) of a line indented by `indent`
+    /// characters, `None` when the code doesn't start its line: the following lines are then
+    /// dedented by their common indentation
+    pub fn new(text: &str, tokens: TokenStream, start_line: usize, indent: Option<usize>) -> Self {
+        let mut literal_lines = HashSet::new();
+        multi_line_literal_lines(tokens, start_line, &mut literal_lines);
+
+        let indent = indent.unwrap_or_else(|| {
+            text.split('\n')
+                .enumerate()
+                .skip(1)
+                .filter(|(idx, line)| !literal_lines.contains(idx) && !line.trim().is_empty())
+                .map(|(_, line)| line.chars().take_while(|c| *c == ' ' || *c == '\t').count())
+                .min()
+                .unwrap_or(0)
+        });
+
+        let lines = text
+            .split('\n')
+            .enumerate()
+            .map(|(idx, line)| match literal_lines.contains(&idx) {
+                true => line.to_string(),
+                false => crate::trivia::dedent(line, indent).to_string(),
+            })
+            .collect();
+        Original {
+            lines,
+            literal_lines,
+        }
+    }
+}
+
+/// Lines (indices from `start_line`) that start inside a multi-line literal
+fn multi_line_literal_lines(tokens: TokenStream, start_line: usize, lines: &mut HashSet<usize>) {
+    for token in tokens {
+        match token {
+            TokenTree::Group(group) => multi_line_literal_lines(group.stream(), start_line, lines),
+            TokenTree::Literal(literal) => {
+                let span = literal.span();
+                lines.extend(span.start().line + 1 - start_line..=span.end().line - start_line);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Formats embedded rust code, returning its lines indented for `shape`
+pub trait RustFormatter {
+    fn format_expr(&self, expr: &Expr, original: &Original, shape: Shape) -> Result<Vec<String>>;
+
+    fn format_pat(&self, pat: &Pat, original: &Original, shape: Shape) -> Result<Vec<String>>;
+
+    fn format_stmts(
+        &self,
+        stmts: &[Stmt],
+        original: &Original,
+        shape: Shape,
+    ) -> Result<Vec<String>>;
+
+    /// Format the code requested since the last call when the formatter defers it, returns true
+    /// if there was some: the macro is then printed again with the results
+    fn flush(&self) -> bool {
+        false
+    }
+}
+
+impl RustFormatterKind {
+    /// Formatter of the rust code of a macro, `path` is the file it comes from
+    pub fn formatter(self, options: &FormatOptions, path: Option<&Path>) -> Box<dyn RustFormatter> {
+        match self {
+            RustFormatterKind::Prettyplease => Box::new(Prettyplease),
+            RustFormatterKind::Rustfmt => Box::new(Rustfmt::new(options, path)),
+            RustFormatterKind::Verbatim => Box::new(Verbatim),
+        }
+    }
+}
+
+/// Formats with prettyplease
+pub struct Prettyplease;
+
+impl Prettyplease {
+    fn unparse(
+        unparse: impl FnOnce() -> Result<Vec<String>> + panic::UnwindSafe,
+    ) -> Result<Vec<String>> {
+        panic::catch_unwind(unparse).map_err(|_| anyhow!("prettyplease panicked"))?
+    }
+}
+
+impl RustFormatter for Prettyplease {
+    fn format_expr(&self, expr: &Expr, _: &Original, shape: Shape) -> Result<Vec<String>> {
+        Prettyplease::unparse(|| unparse_expr(expr, shape.indent, shape.width))
+    }
+
+    fn format_pat(&self, pat: &Pat, _: &Original, shape: Shape) -> Result<Vec<String>> {
+        Prettyplease::unparse(|| unparse_pat(pat, shape.indent, shape.width))
+    }
+
+    fn format_stmts(&self, stmts: &[Stmt], _: &Original, shape: Shape) -> Result<Vec<String>> {
+        Prettyplease::unparse(|| unparse_stmts(stmts, shape.indent, shape.width))
+    }
+}
+
+/// Printing a macro again with the results of rustfmt moves the code after them, which may need
+/// new results, the code still missing after these rounds is formatted right away
+const RUSTFMT_ROUNDS: usize = 4;
+
+/// Formats with the rustfmt executable, see [`rustfmt::program`]. The code of a macro is formatted
+/// by a single rustfmt process: until [`RustFormatter::flush`] runs it, the formatter returns
+/// the output of prettyplease and remembers what to format.
+pub struct Rustfmt {
+    edition: Option<String>,
+    config_path: Option<PathBuf>,
+    /// Lines of the formatted code by code and shape, or the error of rustfmt
+    formatted: RefCell<HashMap<(String, Shape), RustfmtResult>>,
+    pending: RefCell<Vec<Snippet>>,
+    rounds: Cell<usize>,
+}
+
+type RustfmtResult = Result<Vec<String>, String>;
+
+/// Code waiting to be formatted by rustfmt
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct Snippet {
+    code: String,
+    shape: Shape,
+    /// Lines of multi-line literals can't be dedented, the code has to be formatted at its own
+    /// indentation
+    has_literal_lines: bool,
+}
+
+impl Snippet {
+    fn width(&self) -> usize {
+        self.shape.width.max(20)
+    }
+}
+
+impl Rustfmt {
+    pub fn new(options: &FormatOptions, path: Option<&Path>) -> Self {
+        Rustfmt {
+            edition: options
+                .edition
+                .clone()
+                .or_else(|| rustfmt::detect_edition(path)),
+            config_path: options.rustfmt_config_path.clone(),
+            formatted: RefCell::default(),
+            pending: RefCell::default(),
+            rounds: Cell::new(0),
+        }
+    }
+
+    /// Lines of `code` formatted for `shape`, or `placeholder` until they are known
+    fn lines(
+        &self,
+        code: TokenStream,
+        original: &Original,
+        shape: Shape,
+        placeholder: impl FnOnce() -> Result<Vec<String>>,
+    ) -> Result<Vec<String>> {
+        let snippet = Snippet {
+            code: code.to_string(),
+            shape,
+            has_literal_lines: !original.literal_lines.is_empty(),
+        };
+        let key = (snippet.code.clone(), shape);
+        if let Some(formatted) = self.formatted.borrow().get(&key) {
+            return formatted.clone().map_err(|err| anyhow!(err));
+        }
+
+        if self.rounds.get() >= RUSTFMT_ROUNDS {
+            let formatted = self.run_one(&snippet);
+            self.formatted.borrow_mut().insert(key, formatted.clone());
+            return formatted.map_err(|err| anyhow!(err));
+        }
+        self.pending.borrow_mut().push(snippet);
+        placeholder().or_else(|_| Ok(Verbatim::reindent(original, shape)))
+    }
+
+    fn run_one(&self, snippet: &Snippet) -> RustfmtResult {
+        self.run(std::slice::from_ref(snippet))
+            .and_then(|mut lines| lines.pop().context("unexpected rustfmt output"))
+            .map_err(|err| format!("{err:#}"))
+    }
+
+    /// Format `snippets` in one rustfmt run, each one in its own function nested in blocks to
+    /// get its indentation. The narrower ones are nested deeper, as the width is the one of the
+    /// widest snippet.
+    fn run(&self, snippets: &[Snippet]) -> Result<Vec<Vec<String>>> {
+        let width = snippets.iter().map(Snippet::width).max().unwrap_or(0);
+        let depths: Vec<usize> = snippets
+            .iter()
+            .map(|snippet| snippet.shape.indent + (width - snippet.width()).div_ceil(INDENT.len()))
+            .collect();
+
+        let mut source = String::new();
+        for (idx, (snippet, depth)) in snippets.iter().zip(&depths).enumerate() {
+            // the comments keep rustfmt from collapsing the blocks
+            source += &format!("fn __maudfmt_{idx}() {{\n");
+            source += &"{\n//\n".repeat(*depth);
+            source += &snippet.code;
+            source += &"\n}".repeat(*depth);
+            source += "\n}\n";
+        }
+
+        // the nesting and the markup are indented by `INDENT`, whatever the configuration says
+        let args = [
+            OsString::from("--config"),
+            OsString::from(format!(
+                "max_width={width},tab_spaces={},hard_tabs=false",
+                INDENT.len()
+            )),
+        ];
+        let formatted = rustfmt::run(
+            &source,
+            self.edition.as_deref(),
+            self.config_path.as_deref(),
+            &args,
+        )?;
+
+        let mut functions: Vec<Vec<&str>> = Vec::new();
+        for line in formatted.lines() {
+            match functions.last_mut() {
+                Some(function) if !line.starts_with("fn __maudfmt_") => function.push(line),
+                _ => functions.push(vec![line]),
+            }
+        }
+        if functions.len() != snippets.len() {
+            bail!("unexpected rustfmt output");
+        }
+
+        functions
+            .into_iter()
+            .zip(snippets.iter().zip(depths))
+            .map(|(mut lines, (snippet, depth))| {
+                while lines.last().is_some_and(|line| line.is_empty()) {
+                    lines.pop();
+                }
+                let (start, end) = (1 + 2 * depth, lines.len().saturating_sub(1 + depth));
+                if start > end {
+                    bail!("unexpected rustfmt output");
+                }
+                let extra_indent = (depth - snippet.shape.indent) * INDENT.len();
+                Ok(lines[start..end]
+                    .iter()
+                    .map(|line| crate::trivia::dedent(line, extra_indent).to_string())
+                    .collect())
+            })
+            .collect()
+    }
+}
+
+impl RustFormatter for Rustfmt {
+    fn format_expr(&self, expr: &Expr, original: &Original, shape: Shape) -> Result<Vec<String>> {
+        self.lines(quote!(#expr), original, shape, || {
+            Prettyplease.format_expr(expr, original, shape)
+        })
+    }
+
+    fn format_pat(&self, pat: &Pat, original: &Original, shape: Shape) -> Result<Vec<String>> {
+        let mut lines = self.lines(quote!(let #pat;), original, shape, || {
+            Prettyplease.format_pat(pat, original, shape)
+        })?;
+        let first = lines.first_mut().context("empty pattern")?;
+        *first = first.trim_start().trim_start_matches("let ").to_string();
+        let last = lines.last_mut().context("empty pattern")?;
+        *last = last.trim_end_matches(';').to_string();
+        Ok(lines)
+    }
+
+    fn format_stmts(
+        &self,
+        stmts: &[Stmt],
+        original: &Original,
+        shape: Shape,
+    ) -> Result<Vec<String>> {
+        self.lines(quote!(#(#stmts)*), original, shape, || {
+            Prettyplease.format_stmts(stmts, original, shape)
+        })
+    }
+
+    fn flush(&self) -> bool {
+        let pending: BTreeSet<Snippet> = mem::take(&mut *self.pending.borrow_mut())
+            .into_iter()
+            .collect();
+        if pending.is_empty() {
+            return false;
+        }
+        self.rounds.set(self.rounds.get() + 1);
+
+        // code with multi-line literals is formatted with the others of the same width
+        let mut batches: BTreeMap<Option<usize>, Vec<Snippet>> = BTreeMap::new();
+        for snippet in pending {
+            let batch = snippet.has_literal_lines.then(|| snippet.width());
+            batches.entry(batch).or_default().push(snippet);
+        }
+
+        let mut formatted = self.formatted.borrow_mut();
+        for snippets in batches.into_values() {
+            let results: Vec<RustfmtResult> = match self.run(&snippets) {
+                Ok(lines) => lines.into_iter().map(Ok).collect(),
+                // a snippet rustfmt can't format doesn't fail the others
+                Err(_) => snippets
+                    .iter()
+                    .map(|snippet| self.run_one(snippet))
+                    .collect(),
+            };
+            for (snippet, result) in snippets.into_iter().zip(results) {
+                formatted.insert((snippet.code, snippet.shape), result);
+            }
+        }
+        true
+    }
+}
+
+/// Keeps the code as written, only re-indented
+pub struct Verbatim;
+
+impl Verbatim {
+    pub fn reindent(original: &Original, shape: Shape) -> Vec<String> {
+        let indent = INDENT.repeat(shape.indent + 1);
+        original
+            .lines
+            .iter()
+            .enumerate()
+            .map(|(idx, line)| {
+                // trailing whitespace before a line inside a literal is part of it
+                let line = match original.literal_lines.contains(&(idx + 1)) {
+                    true => line.as_str(),
+                    false => line.trim_end(),
+                };
+                match idx {
+                    0 => format!("{indent}{}", line.trim_start()),
+                    _ if original.literal_lines.contains(&idx) => line.to_string(),
+                    _ if line.is_empty() => String::new(),
+                    _ => format!("{indent}{line}"),
+                }
+            })
+            .collect()
+    }
+}
+
+impl RustFormatter for Verbatim {
+    fn format_expr(&self, _: &Expr, original: &Original, shape: Shape) -> Result<Vec<String>> {
+        Ok(Verbatim::reindent(original, shape))
+    }
+
+    fn format_pat(&self, _: &Pat, original: &Original, shape: Shape) -> Result<Vec<String>> {
+        Ok(Verbatim::reindent(original, shape))
+    }
+
+    fn format_stmts(&self, _: &[Stmt], original: &Original, shape: Shape) -> Result<Vec<String>> {
+        Ok(Verbatim::reindent(original, shape))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::LazyLock;
+
+    use crate::{format::FormatOptions, testing::*};
+
+    use super::*;
+
+    static VERBATIM_OPTIONS: LazyLock<FormatOptions> = LazyLock::new(|| FormatOptions {
+        rust_formatter: RustFormatterKind::Verbatim,
+        ..Default::default()
+    });
+
+    test_with_options!(
+        verbatim_rust_code_is_reindented,
+        VERBATIM_OPTIONS,
+        r##"
+        html! {
+        @for (i,item) in items { (f(i,item)) }
+          ({ let y = compute(  1,2 ); y })
+              ({
+                  let text = r#"
+  kept
+"#;
+                  text
+              })
+        }
+        "##,
+        r##"
+        html! {
+            @for (i,item) in items { (f(i,item)) }
+            ({ let y = compute(  1,2 ); y })
+            ({
+                let text = r#"
+  kept
+"#;
+                text
+            })
+        }
+        "##
+    );
+
+    test_with_options!(
+        verbatim_code_after_markup_is_reindented,
+        VERBATIM_OPTIONS,
+        r#"
+        html! { div { (compute(
+                first,
+                second,
+            )) } }
+        "#,
+        r#"
+        html! {
+            div {
+                ({
+                    compute(
+                        first,
+                        second,
+                    )
+                })
+            }
+        }
+        "#
+    );
+
+    /// rustfmt is not installed everywhere the tests run
+    fn rustfmt_installed() -> bool {
+        std::process::Command::new(rustfmt::program())
+            .arg("--version")
+            .output()
+            .is_ok_and(|output| output.status.success())
+    }
+
+    #[test]
+    fn rustfmt_indents_for_shape() {
+        if !rustfmt_installed() {
+            eprintln!("rustfmt is not installed, skipping");
+            return;
+        }
+        let expr: Expr = syn::parse_str("call(first_argument, second_argument)").unwrap();
+        let narrow = Shape {
+            indent: 2,
+            width: 40,
+        };
+        let wide = Shape {
+            indent: 1,
+            width: 100,
+        };
+        let rustfmt = Rustfmt::new(&FormatOptions::default(), None);
+
+        // formatted together when flushed
+        rustfmt
+            .format_expr(&expr, &Original::default(), narrow)
+            .unwrap();
+        rustfmt
+            .format_expr(&expr, &Original::default(), wide)
+            .unwrap();
+        assert!(rustfmt.flush());
+        assert!(!rustfmt.flush());
+
+        assert_eq!(
+            rustfmt
+                .format_expr(&expr, &Original::default(), narrow)
+                .unwrap(),
+            vec![
+                "            call(",
+                "                first_argument,",
+                "                second_argument,",
+                "            )",
+            ]
+        );
+        assert_eq!(
+            rustfmt
+                .format_expr(&expr, &Original::default(), wide)
+                .unwrap(),
+            vec!["        call(first_argument, second_argument)"]
+        );
+    }
+}
@@ -1,20 +1,73 @@
-use std::ops::Range;
+use std::{
+    cell::Cell,
+    fmt::Display,
+    ops::Range,
+    path::{Path, PathBuf},
+};
 
 use crate::ast::{DiagnosticParse, Element};
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, anyhow};
 use crop::Rope;
+use proc_macro2::{LineColumn, TokenStream};
 use syn::{
     parse::{ParseStream, Parser},
     spanned::Spanned,
 };
 
-use crate::{ast::Markups, collect::MaudMacro, print::print};
+use crate::{
+    ast::Markups,
+    collect::MaudMacro,
+    guard::{catch_panic, check_macro},
+    print::{print, print_fragment},
+    trivia::TriviaMap,
+};
 
 const IGNORE_PLACEHOLDER: &str = "\"__MAUDFMT_IGNORED_PLACEHOLDER__\"";
 
+#[derive(Clone, Hash)]
 pub struct FormatOptions {
     pub line_length: usize,
     pub macro_names: Vec<String>,
+    /// Remove `// maudfmt: expand` style directives from the output
+    pub strip_layout_directives: bool,
+    /// Keep the whole macro on a single line when it fits
+    pub single_line_macros: bool,
+    /// Format the examples of `///` and `//!` doc comments
+    pub format_doc_comments: bool,
+    pub newline_style: NewlineStyle,
+    /// Formatter of the rust code inside the markup
+    pub rust_formatter: RustFormatterKind,
+    /// Edition given to rustfmt formatting the rust code, detected from the closest `Cargo.toml`
+    /// when missing
+    pub edition: Option<String>,
+    /// Configuration file given to rustfmt formatting the rust code
+    pub rustfmt_config_path: Option<PathBuf>,
+}
+
+/// Line endings of the output, like rustfmt's `newline_style`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, clap::ValueEnum)]
+pub enum NewlineStyle {
+    /// Keep the line endings of the file, based on its first line break
+    #[default]
+    Auto,
+    /// `\n`
+    Unix,
+    /// `\r\n`
+    Windows,
+    /// `\r\n` on Windows, `\n` elsewhere
+    Native,
+}
+
+/// Formatter of the rust code embedded in the markup: splices, conditions, patterns and `@let`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, clap::ValueEnum)]
+pub enum RustFormatterKind {
+    /// prettyplease, built in
+    #[default]
+    Prettyplease,
+    /// The rustfmt executable, or the one in the `RUSTFMT` environment variable
+    Rustfmt,
+    /// Keep the code as written, only re-indented
+    Verbatim,
 }
 
 impl Default for FormatOptions {
@@ -22,6 +75,47 @@ impl Default for FormatOptions {
         FormatOptions {
             line_length: 100,
             macro_names: vec![String::from("maud::html"), String::from("html")],
+            strip_layout_directives: false,
+            single_line_macros: false,
+            format_doc_comments: false,
+            newline_style: NewlineStyle::Auto,
+            rust_formatter: RustFormatterKind::Prettyplease,
+            edition: None,
+            rustfmt_config_path: None,
+        }
+    }
+}
+
+/// Options overridden for a single macro, see `collect::parse_overrides_comment`
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FormatOverrides {
+    pub line_length: Option<usize>,
+    pub single_line_macros: Option<bool>,
+    pub strip_layout_directives: Option<bool>,
+}
+
+impl FormatOverrides {
+    /// Values set in `other` take precedence
+    pub fn merge(&self, other: &FormatOverrides) -> FormatOverrides {
+        FormatOverrides {
+            line_length: other.line_length.or(self.line_length),
+            single_line_macros: other.single_line_macros.or(self.single_line_macros),
+            strip_layout_directives: other
+                .strip_layout_directives
+                .or(self.strip_layout_directives),
+        }
+    }
+
+    pub fn apply(&self, options: &FormatOptions) -> FormatOptions {
+        FormatOptions {
+            line_length: self.line_length.unwrap_or(options.line_length),
+            strip_layout_directives: self
+                .strip_layout_directives
+                .unwrap_or(options.strip_layout_directives),
+            single_line_macros: self
+                .single_line_macros
+                .unwrap_or(options.single_line_macros),
+            ..options.clone()
         }
     }
 }
@@ -35,7 +129,21 @@ struct TextEdit {
 pub fn format_source(
     source: &mut Rope,
     macros: Vec<MaudMacro<'_>>,
+    path: Option<&Path>,
     options: &FormatOptions,
+) -> String {
+    format_macros(source, macros, path, |mac, source| {
+        format_macro(mac, source, path, options)
+    })
+}
+
+/// Replace each macro by the result of `format`, a macro it fails or panics on is reported and
+/// left unchanged
+fn format_macros(
+    source: &mut Rope,
+    macros: Vec<MaudMacro<'_>>,
+    path: Option<&Path>,
+    format: impl Fn(&MaudMacro, &Rope) -> Result<String>,
 ) -> String {
     let mut edits = Vec::new();
 
@@ -46,12 +154,12 @@ pub fn format_source(
         let start_byte = line_column_to_byte(source, start);
         let end_byte = line_column_to_byte(source, end);
 
-        match format_macro(&maud_mac, source, options) {
+        match catch_panic(|| format(&maud_mac, source)) {
             Ok(new_text) => edits.push(TextEdit {
                 range: start_byte..end_byte,
                 new_text,
             }),
-            Err(e) => eprintln!("{e}"),
+            Err(e) => report(path, start, format!("{e:#}")),
         }
     }
 
@@ -71,15 +179,95 @@ pub fn format_source(
     source.to_string()
 }
 
-fn format_macro(mac: &MaudMacro, source: &Rope, options: &FormatOptions) -> Result<String> {
+thread_local! {
+    /// Number of diagnostics reported on this thread
+    static DIAGNOSTICS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Run `f` and return the number of diagnostics it reported, macros it left unformatted
+pub fn count_diagnostics<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = DIAGNOSTICS.get();
+    let result = f();
+    (result, DIAGNOSTICS.get() - before)
+}
+
+/// Print a diagnostic about the code at `loc`, as `path:line:column: message`
+pub fn report(path: Option<&Path>, loc: LineColumn, message: impl Display) {
+    DIAGNOSTICS.set(DIAGNOSTICS.get() + 1);
+    let (line, column) = (loc.line, loc.column + 1);
+    match path {
+        Some(path) => eprintln!("{}:{line}:{column}: {message}", path.display()),
+        None => eprintln!("{line}:{column}: {message}"),
+    }
+}
+
+fn format_macro(
+    mac: &MaudMacro,
+    source: &Rope,
+    path: Option<&Path>,
+    options: &FormatOptions,
+) -> Result<String> {
+    let options = &mac.overrides.apply(options);
     let mut diagnostics = Vec::new();
     let markups: Markups<Element> = Parser::parse2(
         |input: ParseStream| Markups::diagnostic_parse(input, &mut diagnostics),
         mac.macro_.tokens.clone(),
     )
     .context("Failed to parse maud macro")?;
+    let trivia = TriviaMap::from_macro(source, mac.macro_);
+    let formatter = options.rust_formatter.formatter(options, path);
+    let formatted = print(
+        markups,
+        mac,
+        source,
+        &trivia,
+        path,
+        options,
+        formatter.as_ref(),
+    );
+    check_macro(mac.macro_, &trivia, &formatted, options)?;
+
+    Ok(formatted)
+}
+
+pub fn format_fragment(
+    source: &str,
+    base_indent: usize,
+    options: &FormatOptions,
+) -> Result<String> {
+    let tokens: TokenStream = source
+        .parse()
+        .map_err(|e| anyhow!("Failed to tokenize maud markup: {e}"))?;
+
+    let mut diagnostics = Vec::new();
+    let markups: Markups<Element> = Parser::parse2(
+        |input: ParseStream| Markups::diagnostic_parse(input, &mut diagnostics),
+        tokens.clone(),
+    )
+    .context("Failed to parse maud markup")?;
 
-    Ok(print(markups, mac, source, options))
+    if markups.markups.is_empty() {
+        // nothing to format, only comments or whitespace
+        return Ok(source.to_string());
+    }
+
+    let rope = Rope::from(source);
+    let trivia = TriviaMap::from_fragment(&rope, tokens);
+    let formatter = options.rust_formatter.formatter(options, None);
+    let mut formatted = catch_panic(|| {
+        Ok(print_fragment(
+            markups,
+            &rope,
+            &trivia,
+            base_indent,
+            options,
+            formatter.as_ref(),
+        ))
+    })?;
+    if source.ends_with('\n') {
+        formatted.push('\n');
+    }
+    Ok(formatted)
 }
 
 pub fn line_column_to_byte(source: &Rope, point: proc_macro2::LineColumn) -> usize {
@@ -145,7 +333,53 @@ pub fn reinsert_ignored_lines_in_source(formatted_source: &str, ignore_info: &[&
 
 #[cfg(test)]
 mod test {
-    use crate::testing::*;
+    use crop::Rope;
+
+    use super::*;
+    use crate::{collect::collect_macros_from_file, testing::*};
+
+    #[test]
+    fn panicking_macro_left_unchanged() {
+        let source = r#"
+fn page() -> Markup {
+    html!{p{"a"}};
+    panicking!{p{"b"}};
+    html!{p{"c"}}
+}
+"#;
+        let options = FormatOptions {
+            macro_names: vec!["html".to_string(), "panicking".to_string()],
+            ..Default::default()
+        };
+        let ast = syn::parse_file(source).unwrap();
+        let (mut rope, macros, _) =
+            collect_macros_from_file(&ast, Rope::from(source), &options.macro_names);
+
+        let (formatted, diagnostics) = count_diagnostics(|| {
+            format_macros(&mut rope, macros, None, |mac, source| {
+                if mac.macro_name == "panicking" {
+                    panic!("panicking macro");
+                }
+                format_macro(mac, source, None, &options)
+            })
+        });
+
+        pretty_assertions::assert_eq!(
+            formatted,
+            r#"
+fn page() -> Markup {
+    html! {
+        p { "a" }
+    };
+    panicking!{p{"b"}};
+    html! {
+        p { "c" }
+    }
+}
+"#
+        );
+        assert_eq!(diagnostics, 1);
+    }
 
     test_default!(
         maudfmt_ignore_multiple_lines,
@@ -234,4 +468,63 @@ mod test {
         }
         "#
     );
+
+    test_fragment!(
+        fragment,
+        r#"// navigation
+nav{ul{li{a href="/"{"Home"}}}}
+p{"text"}   // trailing
+"#,
+        r#"// navigation
+nav {
+    ul {
+        li {
+            a href="/" { "Home" }
+        }
+    }
+}
+p { "text" }  // trailing
+"#
+    );
+
+    test_fragment!(
+        fragment_comments_and_blank_lines,
+        r#"
+// leading blank line is dropped
+h1 {"title"}
+
+"text"
+// final comment
+"#,
+        r#"// leading blank line is dropped
+h1 { "title" }
+
+"text"
+// final comment
+"#
+    );
+
+    test_fragment!(
+        fragment_leading_blank_lines,
+        "\n\n@if x { \"a\" }\n\np { \"b\" }\n",
+        "@if x { \"a\" }\n\np { \"b\" }\n"
+    );
+
+    test_fragment!(
+        fragment_maudfmt_ignore,
+        r#"p {"formatted" }
+// maudfmt-ignore
+div class="unformatted"   id="test" { "content" }
+"#,
+        r#"p { "formatted" }
+// maudfmt-ignore
+div class="unformatted"   id="test" { "content" }
+"#
+    );
+
+    test_fragment!(
+        fragment_only_comments,
+        "// nothing here\n",
+        "// nothing here\n"
+    );
 }
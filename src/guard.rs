@@ -0,0 +1,342 @@
+//! Safety guard: a formatted macro must keep the tokens and the comments of the original one,
+//! otherwise it is left as it was. So is a macro whose formatting panics.
+
+use std::panic::{self, AssertUnwindSafe};
+
+use anyhow::{Context, Result, anyhow};
+use crop::Rope;
+use proc_macro2::{Delimiter, TokenStream, TokenTree};
+use quote::ToTokens as _;
+use syn::{
+    Expr, ExprBlock, Ident, LitStr, Macro, Stmt,
+    parse::{ParseStream, Parser},
+    punctuated::Punctuated,
+    spanned::Spanned as _,
+};
+
+use crate::{
+    ast::{
+        Attribute, AttributeType, Block, ControlFlowKind, DiagnosticParse, Element, ElementBody,
+        HtmlName, HtmlNameFragment, HtmlNameOrMarkup, IfExpr, IfOrBlock, Markup, Markups,
+    },
+    format::FormatOptions,
+    print::directive::is_layout_directive,
+    trivia::TriviaMap,
+};
+
+const BUG_REPORT: &str =
+    "this is a bug, please report it at https://github.com/jeosas/maudfmt/issues";
+
+/// Runs `format`, turning a panic into an error so a single macro can't abort the whole run
+pub fn catch_panic<T>(format: impl FnOnce() -> Result<T>) -> Result<T> {
+    panic::catch_unwind(AssertUnwindSafe(format)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        Err(anyhow!(
+            "formatting panicked ({message}), the macro was left unchanged: {BUG_REPORT}"
+        ))
+    })
+}
+
+/// Returns an error if `formatted`, the new text of `original`, changed its tokens or comments
+pub fn check_macro(
+    original: &Macro,
+    trivia: &TriviaMap,
+    formatted: &str,
+    options: &FormatOptions,
+) -> Result<()> {
+    let new: Macro = syn::parse_str(formatted)
+        .map_err(|e| anyhow!("formatted macro can't be parsed ({e}), {BUG_REPORT}"))?;
+
+    let original_tokens = normalized_tokens(original.tokens.clone())?;
+    let new_tokens = normalized_tokens(new.tokens.clone())
+        .with_context(|| format!("formatted macro can't be parsed, {BUG_REPORT}"))?;
+    if original_tokens != new_tokens {
+        return Err(anyhow!(
+            "formatting would change the tokens of the macro, it was left unchanged: {BUG_REPORT}"
+        ));
+    }
+
+    let original_comments = normalized_comments(trivia, options);
+    let new_trivia = TriviaMap::from_macro(&Rope::from(formatted), &new);
+    if original_comments != normalized_comments(&new_trivia, options) {
+        return Err(anyhow!(
+            "formatting would lose or change comments of the macro, it was left unchanged: {BUG_REPORT}"
+        ));
+    }
+
+    Ok(())
+}
+
+/// Tokens of the macro body, without the differences the formatter is allowed to make
+fn normalized_tokens(tokens: TokenStream) -> Result<Vec<String>> {
+    let mut markups: Markups<Element> = Parser::parse2(
+        |input: ParseStream| Markups::diagnostic_parse(input, &mut Vec::new()),
+        tokens,
+    )?;
+    normalize_markups(&mut markups);
+
+    let mut texts = Vec::new();
+    flatten(markups.to_token_stream(), &mut texts);
+    Ok(texts)
+}
+
+/// Token texts, ignoring trailing commas, except the one of a single element tuple, and commas
+/// after a block ending a match arm
+fn flatten(tokens: TokenStream, texts: &mut Vec<String>) {
+    let mut after_arm_block = false;
+    for token in tokens {
+        match token {
+            TokenTree::Group(group) => {
+                let (open, close) = match group.delimiter() {
+                    Delimiter::Parenthesis => ("(", ")"),
+                    Delimiter::Brace => ("{", "}"),
+                    Delimiter::Bracket => ("[", "]"),
+                    Delimiter::None => ("", ""),
+                };
+                let is_arm_block = group.delimiter() == Delimiter::Brace
+                    && matches!(texts.as_slice(), [.., eq, gt] if eq == "=" && gt == ">");
+                let commas = group
+                    .stream()
+                    .into_iter()
+                    .filter(
+                        |token| matches!(token, TokenTree::Punct(punct) if punct.as_char() == ','),
+                    )
+                    .count();
+                // `(x,)` is a tuple, `f(x,)` and `Some(x,)` are not
+                let is_call = texts.last().is_some_and(|text| {
+                    text == ")" || text == "]" || syn::parse_str::<Ident>(text).is_ok()
+                });
+                let maybe_tuple =
+                    group.delimiter() == Delimiter::Parenthesis && commas == 1 && !is_call;
+
+                texts.push(open.to_string());
+                flatten(group.stream(), texts);
+                if !maybe_tuple && texts.last().is_some_and(|text| text == ",") {
+                    texts.pop();
+                }
+                texts.push(close.to_string());
+                after_arm_block = is_arm_block;
+                continue;
+            }
+            TokenTree::Punct(punct) if punct.as_char() == ',' && after_arm_block => {}
+            token => texts.push(token.to_string()),
+        }
+        after_arm_block = false;
+    }
+}
+
+/// Attributes are sorted by kind, names are compared as text and multi-line expressions may be
+/// wrapped in a block, like the formatter prints them
+fn normalize_markups(markups: &mut Markups<Element>) {
+    for markup in &mut markups.markups {
+        normalize_markup(markup);
+    }
+}
+
+fn normalize_markup(markup: &mut Markup<Element>) {
+    match markup {
+        Markup::Element(element) => normalize_element(element),
+        Markup::Block(block) => normalize_markups(&mut block.markups),
+        Markup::ControlFlow(control_flow) => match &mut control_flow.kind {
+            ControlFlowKind::If(if_expr) => normalize_if(if_expr),
+            ControlFlowKind::For(for_expr) => {
+                normalize_expr(&mut for_expr.expr);
+                normalize_block(&mut for_expr.body);
+            }
+            ControlFlowKind::While(while_expr) => {
+                normalize_expr(&mut while_expr.cond);
+                normalize_block(&mut while_expr.body);
+            }
+            ControlFlowKind::Match(match_expr) => {
+                normalize_expr(&mut match_expr.expr);
+                for arm in &mut match_expr.arms {
+                    // the arms are printed without commas
+                    arm.comma_token = None;
+                    if let Some((_, guard)) = &mut arm.guard {
+                        normalize_expr(guard);
+                    }
+                    normalize_markup(&mut arm.body);
+                }
+            }
+            ControlFlowKind::Let(_) => {}
+        },
+        markup => normalize_splice(markup),
+    }
+}
+
+fn normalize_splice<E>(markup: &mut Markup<E>) {
+    if let Markup::Splice { expr, .. } = markup {
+        normalize_expr(expr);
+    }
+}
+
+/// `{ expr }` is the same as `expr`
+fn normalize_expr(expr: &mut Expr) {
+    match expr {
+        Expr::Block(ExprBlock {
+            attrs,
+            label: None,
+            block,
+        }) if attrs.is_empty() && matches!(block.stmts.as_slice(), [Stmt::Expr(_, None)]) => {
+            if let Some(Stmt::Expr(mut inner, None)) = block.stmts.pop() {
+                normalize_expr(&mut inner);
+                *expr = inner;
+            }
+        }
+        Expr::Let(expr_let) => normalize_expr(&mut expr_let.expr),
+        Expr::Range(range) => {
+            for bound in [&mut range.start, &mut range.end].into_iter().flatten() {
+                normalize_expr(bound);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn normalize_if(if_expr: &mut IfExpr<Element>) {
+    normalize_expr(&mut if_expr.cond);
+    normalize_block(&mut if_expr.then_branch);
+    match if_expr
+        .else_branch
+        .as_mut()
+        .map(|(_, _, branch)| &mut **branch)
+    {
+        Some(IfOrBlock::If(else_if)) => normalize_if(else_if),
+        Some(IfOrBlock::Block(block)) => normalize_block(block),
+        None => {}
+    }
+}
+
+fn normalize_block(block: &mut Block<Element>) {
+    normalize_markups(&mut block.markups);
+}
+
+fn normalize_element(element: &mut Element) {
+    if let Some(name) = &mut element.name {
+        normalize_name(name);
+    }
+    element.attrs.sort_by_key(|attr| match attr {
+        Attribute::Id { .. } => 0,
+        Attribute::Class { .. } => 1,
+        Attribute::Named { .. } => 2,
+    });
+    for attr in &mut element.attrs {
+        match attr {
+            Attribute::Id { name, .. } | Attribute::Class { name, .. } => match name {
+                HtmlNameOrMarkup::HtmlName(name) => normalize_name(name),
+                HtmlNameOrMarkup::Markup(markup) => normalize_splice(markup),
+            },
+            Attribute::Named { name, attr_type } => {
+                normalize_name(name);
+                if let AttributeType::Normal { value, .. } = attr_type {
+                    normalize_splice(value);
+                }
+            }
+        }
+    }
+    if let ElementBody::Block(block) = &mut element.body {
+        normalize_block(block);
+    }
+}
+
+/// `a`, `"a"` and `r"a"` are the same name
+fn normalize_name(name: &mut HtmlName) {
+    let text = name.to_string();
+    let mut normalized = Punctuated::new();
+    normalized.push_value(HtmlNameFragment::LitStr(LitStr::new(&text, name.span())));
+    name.name = normalized;
+}
+
+/// Comment texts, sorted, without the whitespace the formatter changes
+fn normalized_comments(trivia: &TriviaMap, options: &FormatOptions) -> Vec<String> {
+    let mut comments: Vec<String> = trivia
+        .comments()
+        .iter()
+        .filter(|comment| {
+            !(options.strip_layout_directives
+                && comment.line_text().is_some_and(is_layout_directive))
+        })
+        .map(|comment| match comment.line_text() {
+            Some(text) => format!("//{}", text.trim()),
+            None => comment
+                .text
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" "),
+        })
+        .collect();
+    comments.sort();
+    comments
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn check(original: &str, formatted: &str) -> Result<()> {
+        let source = Rope::from(original);
+        let mac: Macro = syn::parse_str(original).unwrap();
+        let trivia = TriviaMap::from_macro(&source, &mac);
+        check_macro(&mac, &trivia, formatted, &FormatOptions::default())
+    }
+
+    #[test]
+    fn allowed_differences() {
+        let original =
+            "html! { p.a #b title=\"t\" { \"x\" } @match x { A => { \"a\" }, B => (f(1, 2,)) } }";
+        let formatted = "html! {\n    p #b.a title=\"t\" { \"x\" }\n    @match x {\n        A => { \"a\" }\n        B => (f(1, 2))\n    }\n}";
+
+        assert!(check(original, formatted).is_ok());
+    }
+
+    #[test]
+    fn changed_tokens() {
+        let error = check("html! { svg:rect {} }", "html! { svg:circle {} }").unwrap_err();
+        assert!(error.to_string().contains("this is a bug"));
+
+        assert!(check("html! { p { \"a\" } }", "html! { p { \"b\" } }").is_err());
+        assert!(check("html! { (a + b) }", "html! { (a - b) }").is_err());
+        assert!(check("html! { ((x,)) }", "html! { ((x)) }").is_err());
+        assert!(check("html! { @let y = (x,); }", "html! { @let y = (x); }").is_err());
+        assert!(check("html! { (f({ a }, b)) }", "html! { (f({ a } b)) }").is_err());
+    }
+
+    #[test]
+    fn allowed_commas() {
+        assert!(check("html! { (f(a, [b, c,],)) }", "html! { (f(a, [b, c])) }").is_ok());
+        assert!(check("html! { (f(Some(a))) }", "html! { (f(Some(a,),)) }").is_ok());
+        assert!(
+            check(
+                "html! { (match x { A => { 1 }, B => 2 }) @match x { A => \"a\", B => {} } }",
+                "html! { (match x { A => { 1 } B => 2 }) @match x { A => \"a\" B => {} } }"
+            )
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn panic_is_an_error() {
+        let error = catch_panic(|| -> Result<()> { panic!("index out of bounds") }).unwrap_err();
+        assert!(error.to_string().contains("index out of bounds"));
+        assert!(error.to_string().contains("left unchanged"));
+
+        assert_eq!(catch_panic(|| Ok(1)).unwrap(), 1);
+    }
+
+    #[test]
+    fn lost_comments() {
+        assert!(check("html! { \"a\" // comment\n}", "html! { \"a\" }").is_err());
+        assert!(check("html! { (a /* comment */) }", "html! { (a) }").is_err());
+        assert!(
+            check(
+                "html! { \"a\" //comment\n}",
+                "html! {\n    \"a\"  // comment\n}"
+            )
+            .is_ok()
+        );
+    }
+}
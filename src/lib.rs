@@ -1,35 +1,118 @@
+use std::path::Path;
+
 use anyhow::{Context, Result};
 use crop::Rope;
 
 mod collect;
+mod doc_comments;
+mod embedded;
 mod format;
+mod guard;
 mod line_length;
+mod markdown;
+mod newline;
 mod print;
+pub mod rustfmt;
+mod trivia;
 mod unparse;
 mod vendor;
+mod verify;
 
 #[cfg(test)]
 mod testing;
 
 use vendor::ast;
 
-pub use format::FormatOptions;
+pub use format::{FormatOptions, NewlineStyle, RustFormatterKind, count_diagnostics};
+use newline::SourceStyle;
+pub use verify::Unstable;
 
 pub fn try_fmt_file(source: &str, options: &format::FormatOptions) -> Result<String> {
+    try_fmt_file_with_path(source, None, options)
+}
+
+/// Same as [`try_fmt_file`], `path` is the file the source comes from, used to label diagnostics.
+pub fn try_fmt_file_with_path(
+    source: &str,
+    path: Option<&Path>,
+    options: &format::FormatOptions,
+) -> Result<String> {
+    let (style, source) = SourceStyle::normalize(source, options.newline_style);
+    fmt_file(&source, path, options).map(|formatted| style.restore(formatted))
+}
+
+/// Format `source` twice and return the macros formatted differently by the second pass, which
+/// should never happen.
+pub fn try_verify_file(
+    source: &str,
+    path: Option<&Path>,
+    options: &format::FormatOptions,
+) -> Result<Vec<Unstable>> {
+    verify::verify_file(source, path, options)
+}
+
+/// Format a source with `\n` line endings and no BOM
+pub(crate) fn fmt_file(
+    source: &str,
+    path: Option<&Path>,
+    options: &format::FormatOptions,
+) -> Result<String> {
     let (processed_source, ignore_info) = format::preprocess_source_for_ignore(source);
 
     let ast = syn::parse_file(&processed_source).context("Failed to parse source")?;
     let rope = Rope::from(processed_source);
-    let (mut rope, macros) = collect::collect_macros_from_file(&ast, rope, &options.macro_names);
-    let formatted_processed = format::format_source(&mut rope, macros, options);
+    let (mut rope, macros, invalid_overrides) =
+        collect::collect_macros_from_file(&ast, rope, &options.macro_names);
+    for invalid in invalid_overrides {
+        format::report(
+            path,
+            invalid.loc,
+            format!("ignoring invalid maudfmt option `{}`", invalid.setting),
+        );
+    }
+    let formatted_processed = format::format_source(&mut rope, macros, path, options);
 
     // Reinsert ignored lines if any
-    if ignore_info.is_empty() {
-        Ok(formatted_processed)
+    let formatted = if ignore_info.is_empty() {
+        formatted_processed
     } else {
-        Ok(format::reinsert_ignored_lines_in_source(
-            &formatted_processed,
-            &ignore_info,
-        ))
+        format::reinsert_ignored_lines_in_source(&formatted_processed, &ignore_info)
+    };
+
+    if options.format_doc_comments
+        && let Ok(file) = syn::parse_file(&formatted)
+    {
+        return Ok(doc_comments::format_doc_comments(
+            &formatted, &file, options,
+        ));
     }
+    Ok(formatted)
+}
+
+/// Format bare maud markup (the content of a macro, without `html! { }`), indented by
+/// `base_indent` levels.
+pub fn try_fmt_fragment(
+    source: &str,
+    base_indent: usize,
+    options: &format::FormatOptions,
+) -> Result<String> {
+    let (style, source) = SourceStyle::normalize(source, options.newline_style);
+    let (processed_source, ignore_info) = format::preprocess_source_for_ignore(&source);
+
+    let formatted_processed = format::format_fragment(&processed_source, base_indent, options)?;
+
+    // Reinsert ignored lines if any
+    let formatted = if ignore_info.is_empty() {
+        formatted_processed
+    } else {
+        format::reinsert_ignored_lines_in_source(&formatted_processed, &ignore_info)
+    };
+    Ok(style.restore(formatted))
+}
+
+/// Format the maud macros inside the rust code blocks of a markdown document.
+pub fn try_fmt_markdown(source: &str, options: &format::FormatOptions) -> Result<String> {
+    let (style, source) = SourceStyle::normalize(source, options.newline_style);
+    let formatted = markdown::format_markdown(&source, options, markdown::Flavor::Markdown);
+    Ok(style.restore(formatted))
 }
@@ -1,82 +1,332 @@
 use std::{
+    ffi::OsString,
     fs,
-    io::{self, Read, Write as _},
-    path::PathBuf,
-    process::{Command, Stdio},
+    io::{self, Read},
+    path::{Path, PathBuf},
+    process,
 };
 
-use anyhow::{Context, Result, bail};
-use clap::Parser;
-use glob::glob;
-use maudfmt::{FormatOptions, try_fmt_file};
+use anyhow::{Context, Result, anyhow, bail};
+use clap::{Args, Parser, Subcommand};
+use glob::{Pattern, glob};
+use maudfmt::{
+    FormatOptions, NewlineStyle, RustFormatterKind, Unstable, count_diagnostics,
+    rustfmt::{self, Rustfmt, RustfmtError, RustfmtStage},
+    try_fmt_file_with_path, try_fmt_fragment, try_fmt_markdown, try_verify_file,
+};
+
+use crate::cache::Cache;
+
+mod cache;
+mod watch;
 
 #[derive(Parser)]
-#[command(version, about, long_about = None, arg_required_else_help=true)]
+#[command(
+    version,
+    about,
+    long_about = None,
+    arg_required_else_help = true,
+    args_conflicts_with_subcommands = true
+)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// A space separated list of file, directory or glob
-    #[arg(value_name = "FILE", required_unless_present = "stdin")]
+    #[arg(value_name = "FILE", required_unless_present_any = ["stdin", "files_from"])]
     files: Option<Vec<String>>,
 
+    /// Read the paths to format from a file, one per line (`-` for stdin); paths are not globs
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["stdin", "watch"])]
+    files_from: Option<PathBuf>,
+
+    /// Paths read with `--files-from` are separated by NUL characters, like `git ls-files -z`
+    #[arg(short = '0', long, default_value = "false", requires = "files_from")]
+    null: bool,
+
     /// Format stdin and write to stdout
     #[arg(short, long, default_value = "false")]
     stdin: bool,
 
+    /// Don't write anything, exit with an error if some input is not formatted
+    #[arg(long, default_value = "false")]
+    check: bool,
+
+    /// Keep running and format the files every time they are saved
+    #[arg(long, default_value = "false", conflicts_with_all = ["stdin", "check"])]
+    watch: bool,
+
+    /// Don't write anything, format twice and report with a diff any macro changed by the second pass
+    #[arg(
+        long,
+        default_value = "false",
+        conflicts_with_all = ["check", "watch", "rustfmt"]
+    )]
+    verify: bool,
+
+    /// Don't skip the files known to be formatted
+    #[arg(long, default_value = "false")]
+    no_cache: bool,
+
+    /// Directory of the cache of formatted files (default: `target/maudfmt` of the closest cargo project)
+    #[arg(long, value_name = "DIR", conflicts_with = "no_cache")]
+    cache_dir: Option<PathBuf>,
+
+    /// Path of the file read on stdin, for configuration lookup, diagnostics and the input mode
+    /// (`.rs`, `.md` or `.maud`)
+    #[arg(long, value_name = "PATH", requires = "stdin")]
+    stdin_filepath: Option<PathBuf>,
+
+    /// Treat stdin as bare maud markup (the content of a macro), like `.maud` files
+    #[arg(
+        long,
+        default_value = "false",
+        requires = "stdin",
+        conflicts_with = "rustfmt"
+    )]
+    fragment: bool,
+
+    /// Run rustfmt after (default) or before maudfmt
+    #[arg(
+        long,
+        value_name = "STAGE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "after"
+    )]
+    rustfmt: Option<RustfmtStage>,
+
+    /// Rust edition passed to rustfmt, with `--rustfmt` or `--rust-formatter rustfmt` (default:
+    /// from the closest Cargo.toml)
+    #[arg(long)]
+    edition: Option<String>,
+
+    /// Path of the rustfmt configuration file passed to rustfmt, with `--rustfmt` or
+    /// `--rust-formatter rustfmt`
+    #[arg(long, value_name = "PATH")]
+    config_path: Option<PathBuf>,
+
+    #[command(flatten)]
+    format: FormatArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Act as rustfmt, formatting stdin with rustfmt then maudfmt (for rust-analyzer `overrideCommand`)
+    RustfmtWrapper(RustfmtWrapperArgs),
+}
+
+#[derive(Args)]
+struct RustfmtWrapperArgs {
+    #[command(flatten)]
+    format: FormatArgs,
+
+    /// Arguments passed to rustfmt, like `--edition 2021`
+    #[arg(
+        value_name = "RUSTFMT_ARGS",
+        trailing_var_arg = true,
+        allow_hyphen_values = true
+    )]
+    rustfmt_args: Vec<OsString>,
+}
+
+#[derive(Args)]
+struct FormatArgs {
     /// Comma-separated list of macro names (overriding html and maud::html)
     #[arg(short, long, value_delimiter = ',', default_value = None)]
     macro_names: Option<Vec<String>>,
 
-    /// Run rustfmt after maudfmt
-    #[arg(long, default_value = "false")]
-    rustfmt: bool,
-
     /// Maximum line length
     #[arg(long)]
     line_length: Option<usize>,
+
+    /// Remove `// maudfmt: expand` and `// maudfmt: collapse` directives after applying them
+    #[arg(long, default_value = "false")]
+    strip_layout_directives: bool,
+
+    /// Keep macros on a single line when they fit
+    #[arg(long, default_value = "false")]
+    single_line_macros: bool,
+
+    /// Format the examples of `///` and `//!` doc comments
+    #[arg(long, default_value = "false")]
+    doc_comments: bool,
+
+    /// Line endings of the output
+    #[arg(long, value_enum, default_value_t = NewlineStyle::Auto)]
+    newline_style: NewlineStyle,
+
+    /// Formatter of the rust code inside the markup (splices, conditions, patterns, `@let`)
+    #[arg(long, value_enum, default_value_t = RustFormatterKind::Prettyplease)]
+    rust_formatter: RustFormatterKind,
+}
+
+impl FormatArgs {
+    fn options(self) -> FormatOptions {
+        let mut format_options = FormatOptions::default();
+        if let Some(macro_names) = self.macro_names {
+            format_options.macro_names = macro_names;
+        }
+        if let Some(line_length) = self.line_length {
+            format_options.line_length = line_length;
+        }
+        format_options.strip_layout_directives = self.strip_layout_directives;
+        format_options.single_line_macros = self.single_line_macros;
+        format_options.format_doc_comments = self.doc_comments;
+        format_options.newline_style = self.newline_style;
+        format_options.rust_formatter = self.rust_formatter;
+        format_options
+    }
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
 
-    let mut format_options = FormatOptions::default();
-    if let Some(macro_names) = cli.macro_names {
-        format_options.macro_names = macro_names;
-    }
-    if let Some(line_length) = cli.line_length {
-        format_options.line_length = line_length;
+    if let Some(Command::RustfmtWrapper(args)) = cli.command {
+        return rustfmt_wrapper(args);
     }
 
-    if cli.stdin {
-        let buf = {
-            let mut buf = String::new();
-            let mut stdin = io::stdin();
-            stdin
-                .read_to_string(&mut buf)
-                .context("Failed to read from stdin")?;
-            buf
-        };
+    let format_options = FormatOptions {
+        edition: cli.edition.clone(),
+        rustfmt_config_path: cli.config_path.clone(),
+        ..cli.format.options()
+    };
+
+    let rustfmt = cli.rustfmt.map(|stage| Rustfmt {
+        stage,
+        edition: cli.edition,
+        config_path: cli.config_path,
+        args: Vec::new(),
+    });
 
-        let mut formatted_buf = try_fmt_file(&buf, &format_options).unwrap_or(buf);
+    if cli.stdin {
+        let buf = read_stdin()?;
 
-        if cli.rustfmt {
-            formatted_buf = run_rustfmt(&formatted_buf).unwrap_or(formatted_buf);
+        let path = cli.stdin_filepath.as_deref();
+        let mode = match path {
+            _ if cli.fragment => InputMode::Fragment,
+            Some(path) => InputMode::from_path(path),
+            None => InputMode::Rust,
+        };
+        if cli.verify {
+            let unstable = mode.verify(&buf, path, &format_options)?;
+            if report_unstable(path.unwrap_or(Path::new("<stdin>")), &unstable) {
+                bail!("stdin is not formatted the same way twice");
+            }
+            return Ok(());
         }
+        let (formatted_buf, diagnostics) =
+            format_input(&buf, path, mode, &format_options, rustfmt.as_ref()).map_err(|err| {
+                match path {
+                    Some(path) => anyhow!("{}: {err:#}", path.display()),
+                    None => err,
+                }
+            })?;
 
-        print!("{formatted_buf}");
+        if cli.check {
+            if diagnostics > 0 {
+                bail!("stdin could not be fully formatted");
+            }
+            if formatted_buf != buf {
+                bail!("stdin is not formatted");
+            }
+        } else {
+            print!("{formatted_buf}");
+        }
     } else {
-        match cli.files {
-            None => bail!("No files provided while not using stdin mode"),
-            Some(files) => {
-                for file in get_file_paths(files)? {
-                    let source = std::fs::read_to_string(&file)?;
-                    let mut formatted_source =
-                        try_fmt_file(&source, &format_options).unwrap_or(source);
+        match (cli.files, cli.files_from) {
+            (None, None) => bail!("No files provided while not using stdin mode"),
+            (Some(files), _) if cli.watch => {
+                watch::watch(files, &format_options, rustfmt.as_ref())?
+            }
+            (files, files_from) => {
+                let mut paths = get_file_paths(files.unwrap_or_default())?;
+                if let Some(files_from) = files_from {
+                    paths.extend(get_literal_file_paths(read_file_list(
+                        &files_from,
+                        cli.null,
+                    )?)?);
+                    paths.sort();
+                    paths.dedup();
+                }
+
+                // rustfmt output depends on its own version and configuration, don't cache it
+                let uses_rustfmt = rustfmt.is_some()
+                    || format_options.rust_formatter == RustFormatterKind::Rustfmt;
+                let cache = if cli.no_cache || cli.verify || uses_rustfmt {
+                    None
+                } else {
+                    cli.cache_dir
+                        .or_else(Cache::default_dir)
+                        .map(|dir| Cache::new(dir, &format_options))
+                };
 
-                    if cli.rustfmt {
-                        formatted_source =
-                            run_rustfmt(&formatted_source).unwrap_or(formatted_source);
+                let mut failed = false;
+                let mut unformatted = false;
+                let mut unstable = false;
+                for file in paths {
+                    let source = std::fs::read_to_string(&file)?;
+                    let mode = InputMode::from_path(&file);
+                    if cli.verify {
+                        match mode.verify(&source, Some(&file), &format_options) {
+                            Ok(macros) => unstable |= report_unstable(&file, &macros),
+                            Err(err) => {
+                                eprintln!("{}: {err:#}", file.display());
+                                failed = true;
+                            }
+                        }
+                        continue;
+                    }
+                    if let Some(cache) = &cache
+                        && cache.is_formatted(&source, mode)
+                    {
+                        continue;
                     }
 
-                    fs::write(file, &formatted_source)?;
+                    let (formatted_source, diagnostics) = match format_input(
+                        &source,
+                        Some(&file),
+                        mode,
+                        &format_options,
+                        rustfmt.as_ref(),
+                    ) {
+                        Ok(formatted) => formatted,
+                        Err(err) => {
+                            // keep going, the other files can still be formatted
+                            eprintln!("{}: {err:#}", file.display());
+                            failed = true;
+                            continue;
+                        }
+                    };
+
+                    // like `cargo fmt --check`, the macros left unformatted are failures
+                    if cli.check && diagnostics > 0 {
+                        failed = true;
+                    }
+                    if formatted_source != source {
+                        if cli.check {
+                            println!("Would reformat {}", file.display());
+                            unformatted = true;
+                            continue;
+                        }
+                        fs::write(&file, &formatted_source)?;
+                    }
+                    // files with diagnostics are formatted again, to report them again
+                    if let Some(cache) = &cache
+                        && diagnostics == 0
+                        && let Err(err) = cache.set_formatted(&formatted_source, mode)
+                    {
+                        eprintln!("Warning: failed to update the cache: {err}");
+                    }
+                }
+                if failed {
+                    bail!("Failed to format some files");
+                }
+                if unformatted {
+                    bail!("Some files are not formatted");
+                }
+                if unstable {
+                    bail!("Some files are not formatted the same way twice");
                 }
             }
         }
@@ -85,9 +335,160 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+/// Behave like rustfmt reading stdin, with maudfmt applied to its output
+fn rustfmt_wrapper(args: RustfmtWrapperArgs) -> Result<()> {
+    if !rustfmt::emits_source(&args.rustfmt_args) {
+        // `--check`, `--version`... can't be combined with maudfmt
+        let status = rustfmt::passthrough(&args.rustfmt_args)?;
+        process::exit(status.code().unwrap_or(1));
+    }
+
+    let rustfmt = Rustfmt {
+        // macros are formatted once rustfmt placed them
+        stage: RustfmtStage::Before,
+        edition: None,
+        config_path: None,
+        args: args.rustfmt_args,
+    };
+    let source = read_stdin()?;
+    match format_input(
+        &source,
+        None,
+        InputMode::Rust,
+        &args.format.options(),
+        Some(&rustfmt),
+    ) {
+        Ok((formatted, _)) => {
+            print!("{formatted}");
+            Ok(())
+        }
+        Err(err) => match err.downcast::<RustfmtError>() {
+            // same output and exit code as rustfmt
+            Ok(RustfmtError::Failed { status, stderr }) => {
+                eprint!("{stderr}");
+                process::exit(status.code().unwrap_or(1));
+            }
+            Ok(err) => Err(err.into()),
+            Err(err) => Err(err),
+        },
+    }
+}
+
+fn read_stdin() -> Result<String> {
+    let mut buf = String::new();
+    io::stdin()
+        .read_to_string(&mut buf)
+        .context("Failed to read from stdin")?;
+    Ok(buf)
+}
+
+/// Format with maudfmt, and with rustfmt at the requested stage for rust sources. Also returns
+/// the number of diagnostics, the macros left unformatted.
+fn format_input(
+    source: &str,
+    path: Option<&Path>,
+    mode: InputMode,
+    options: &FormatOptions,
+    rustfmt: Option<&Rustfmt>,
+) -> Result<(String, usize)> {
+    let maudfmt = |source: &str| match count_diagnostics(|| mode.format(source, path, options)) {
+        (Ok(formatted), diagnostics) => Ok((formatted, diagnostics)),
+        (Err(err), _) => Err(err),
+    };
+
+    match rustfmt {
+        Some(rustfmt) if mode == InputMode::Rust => match rustfmt.stage {
+            RustfmtStage::Before => maudfmt(&rustfmt.run(source, path)?),
+            RustfmtStage::After => match maudfmt(source) {
+                Ok((formatted, diagnostics)) => Ok((rustfmt.run(&formatted, path)?, diagnostics)),
+                Err(err) => {
+                    // rustfmt explains the syntax errors of the sources maudfmt can't parse
+                    rustfmt.run(source, path)?;
+                    Err(err)
+                }
+            },
+        },
+        _ => maudfmt(source),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum InputMode {
+    /// Rust source containing maud macros
+    Rust,
+    /// Bare maud markup, without a surrounding macro
+    Fragment,
+    /// Markdown document with rust code blocks
+    Markdown,
+}
+
+impl InputMode {
+    fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("maud") => InputMode::Fragment,
+            Some("md") => InputMode::Markdown,
+            _ => InputMode::Rust,
+        }
+    }
+
+    fn format(self, source: &str, path: Option<&Path>, options: &FormatOptions) -> Result<String> {
+        match self {
+            InputMode::Rust => try_fmt_file_with_path(source, path, options),
+            InputMode::Fragment => try_fmt_fragment(source, fragment_base_indent(source), options),
+            InputMode::Markdown => try_fmt_markdown(source, options),
+        }
+    }
+
+    /// Format twice, macro by macro for rust sources and as a whole for the other inputs
+    fn verify(
+        self,
+        source: &str,
+        path: Option<&Path>,
+        options: &FormatOptions,
+    ) -> Result<Vec<Unstable>> {
+        if self == InputMode::Rust {
+            return try_verify_file(source, path, options);
+        }
+        let first = self.format(source, path, options)?;
+        let second = self.format(&first, path, options)?;
+        if first == second {
+            return Ok(Vec::new());
+        }
+        Ok(vec![Unstable {
+            line: 1,
+            column: 1,
+            first,
+            second,
+        }])
+    }
+}
+
+/// Print the macros of `path` changed by a second pass, returns true if there are some
+fn report_unstable(path: &Path, unstable: &[Unstable]) -> bool {
+    for unstable in unstable {
+        println!("{}:{unstable}", path.display());
+    }
+    !unstable.is_empty()
+}
+
+/// Indent level of the first non blank line, so that a selection inside a macro keeps its indent
+fn fragment_base_indent(source: &str) -> usize {
+    let indent_chars: Vec<_> = source
+        .lines()
+        .find(|line| !line.trim().is_empty())
+        .unwrap_or_default()
+        .chars()
+        .take_while(|&c| c == ' ' || c == '\t')
+        .collect();
+
+    let tabs = indent_chars.iter().filter(|&&c| c == '\t').count();
+    let spaces = indent_chars.iter().filter(|&&c| c == ' ').count();
+    tabs + spaces / 4
+}
+
 fn get_file_paths(input_patterns: Vec<String>) -> Result<Vec<PathBuf>> {
     let mut paths: Vec<PathBuf> = Vec::new();
-    for pattern in input_patterns.into_iter().map(as_glob_pattern) {
+    for pattern in input_patterns.into_iter().flat_map(as_glob_patterns) {
         for path in glob(&pattern).context(format!("Failed to read glob pattern: {pattern}"))? {
             match path {
                 Ok(value) => paths.push(value),
@@ -95,38 +496,62 @@ fn get_file_paths(input_patterns: Vec<String>) -> Result<Vec<PathBuf>> {
             }
         }
     }
+    // overlapping patterns (`src` and `src/bin`) must not format a file twice
+    paths.sort();
+    paths.dedup();
     Ok(paths)
 }
 
-fn as_glob_pattern(pattern: String) -> String {
+fn as_glob_patterns(pattern: String) -> Vec<String> {
     let is_dir = fs::metadata(&pattern)
         .map(|meta| meta.is_dir())
         .unwrap_or(false);
     if is_dir {
-        return format!("{}/**/*.rs", &pattern.trim_end_matches('/'));
+        return dir_glob_patterns(&pattern);
     }
-    pattern
+    vec![pattern]
 }
 
-fn run_rustfmt(source: &str) -> Option<String> {
-    let mut child = Command::new("rustfmt")
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .spawn()
-        .expect("rustfmt: failed to run rustfmt");
+/// Rust and `.maud` files of `dir`, markdown files are only formatted when named explicitly
+fn dir_glob_patterns(dir: &str) -> Vec<String> {
+    // the directory itself is not a glob, `[id]` or `*` are part of its name
+    let dir = Pattern::escape(dir.trim_end_matches('/'));
+    ["rs", "maud"]
+        .iter()
+        .map(|ext| format!("{dir}/**/*.{ext}"))
+        .collect()
+}
 
-    child
-        .stdin
-        .as_mut()
-        .expect("failed to open stdin")
-        .write_all(source.as_bytes())
-        .expect("failed to write to stdin");
+/// Paths of a `--files-from` list: no glob expansion, directories are expanded like arguments
+fn get_literal_file_paths(paths: Vec<PathBuf>) -> Result<Vec<PathBuf>> {
+    let mut file_paths = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let dir = path.to_str().context("Non utf8 directory path")?;
+            file_paths.extend(get_file_paths(dir_glob_patterns(dir))?);
+        } else {
+            file_paths.push(path);
+        }
+    }
+    Ok(file_paths)
+}
 
-    let output = child.wait_with_output().expect("failed to read stdout");
+/// Paths listed in `list_path` (`-` for stdin), one per line or separated by NUL characters
+fn read_file_list(list_path: &Path, null_separated: bool) -> Result<Vec<PathBuf>> {
+    let list = if list_path == Path::new("-") {
+        read_stdin()?
+    } else {
+        fs::read_to_string(list_path)
+            .with_context(|| format!("Failed to read {}", list_path.display()))?
+    };
 
-    if output.status.success() {
-        Some(String::from_utf8(output.stdout).expect("stdout is not valid utf8"))
+    let paths = if null_separated {
+        list.split('\0').map(PathBuf::from).collect::<Vec<_>>()
     } else {
-        None
-    }
+        list.lines().map(PathBuf::from).collect()
+    };
+    Ok(paths
+        .into_iter()
+        .filter(|path| !path.as_os_str().is_empty())
+        .collect())
 }
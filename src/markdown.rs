@@ -0,0 +1,345 @@
+use crate::{fmt_file, format::FormatOptions};
+
+const SNIPPET_START: &str = "fn __maudfmt_snippet() {\n";
+const SNIPPET_END: &str = "}\n";
+const HIDDEN_MARKER: &str = "// __maudfmt_hidden__";
+
+// rustdoc code block attributes, see https://doc.rust-lang.org/rustdoc/write-documentation/documentation-tests.html#attributes
+const RUSTDOC_ATTRIBUTES: &[&str] = &[
+    "rust",
+    "ignore",
+    "should_panic",
+    "no_run",
+    "compile_fail",
+    "test_harness",
+    "standalone_crate",
+    "edition2015",
+    "edition2018",
+    "edition2021",
+    "edition2024",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flavor {
+    /// Only blocks tagged `rust` or `rs` contain rust code
+    Markdown,
+    /// Untagged blocks contain rust code too
+    Rustdoc,
+}
+
+/// Format the maud macros of every rust code block of a markdown document.
+/// Prose, fences and code that can't be parsed are left untouched.
+pub fn format_markdown(source: &str, options: &FormatOptions, flavor: Flavor) -> String {
+    let mut output = String::with_capacity(source.len());
+    let mut lines = source.split_inclusive('\n');
+
+    while let Some(line) = lines.next() {
+        output.push_str(line);
+
+        let Some(fence) = Fence::open(line) else {
+            continue;
+        };
+
+        let mut code = String::new();
+        let mut closing_line = None;
+        for line in lines.by_ref() {
+            if fence.is_closed_by(line) {
+                closing_line = Some(line);
+                break;
+            }
+            code.push_str(line);
+        }
+
+        // an unclosed code block runs until the end of the document, leave it alone
+        match closing_line {
+            Some(closing_line) if fence.is_rust(flavor) => {
+                output.push_str(&format_code_block(&code, fence.indent, options));
+                output.push_str(closing_line);
+            }
+            Some(closing_line) => {
+                output.push_str(&code);
+                output.push_str(closing_line);
+            }
+            None => output.push_str(&code),
+        }
+    }
+
+    output
+}
+
+struct Fence<'a> {
+    indent: usize,
+    marker: char,
+    len: usize,
+    info: &'a str,
+}
+
+impl<'a> Fence<'a> {
+    fn open(line: &'a str) -> Option<Self> {
+        let (indent, rest) = split_indent(line)?;
+        let marker = rest.chars().next().filter(|&c| c == '`' || c == '~')?;
+        let len = rest.chars().take_while(|&c| c == marker).count();
+        let info = rest[len..].trim();
+
+        if len < 3 || (marker == '`' && info.contains('`')) {
+            return None;
+        }
+
+        Some(Fence {
+            indent,
+            marker,
+            len,
+            info,
+        })
+    }
+
+    fn is_closed_by(&self, line: &str) -> bool {
+        let Some((_, rest)) = split_indent(line) else {
+            return false;
+        };
+        let len = rest.chars().take_while(|&c| c == self.marker).count();
+
+        len >= self.len && rest[len..].trim().is_empty()
+    }
+
+    // `rust`, `rs` or with attributes: `rust,ignore`, `rust edition2021`...
+    fn is_rust(&self, flavor: Flavor) -> bool {
+        let mut attributes = self
+            .info
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|attribute| !attribute.is_empty());
+
+        match flavor {
+            Flavor::Markdown => attributes.next().is_some_and(|lang| {
+                lang.eq_ignore_ascii_case("rust") || lang.eq_ignore_ascii_case("rs")
+            }),
+            Flavor::Rustdoc => attributes.all(|attribute| {
+                RUSTDOC_ATTRIBUTES.contains(&attribute) || attribute.starts_with("ignore-")
+            }),
+        }
+    }
+}
+
+// fences can be indented by up to 3 spaces
+fn split_indent(line: &str) -> Option<(usize, &str)> {
+    let rest = line.trim_start_matches(' ');
+    let indent = line.len() - rest.len();
+    (indent <= 3).then_some((indent, rest))
+}
+
+fn format_code_block(code: &str, indent: usize, options: &FormatOptions) -> String {
+    let dedented: String = code
+        .split_inclusive('\n')
+        .map(|line| {
+            let line_indent = line.len() - line.trim_start_matches(' ').len();
+            &line[line_indent.min(indent)..]
+        })
+        .collect();
+
+    let (code_with_hidden, hidden_count) = reveal_hidden_lines(&dedented);
+    let formatted = format_snippet(&code_with_hidden, options)
+        .and_then(|formatted| hide_revealed_lines(&formatted, hidden_count));
+
+    match formatted {
+        Some(formatted) => formatted
+            .split_inclusive('\n')
+            .map(|line| {
+                if line.trim().is_empty() {
+                    line.to_string()
+                } else {
+                    format!("{}{line}", " ".repeat(indent))
+                }
+            })
+            .collect(),
+        None => code.to_string(),
+    }
+}
+
+/// Turn doctest hidden lines (`# use maud::html;`) into code, marked so they can be hidden again
+fn reveal_hidden_lines(code: &str) -> (String, usize) {
+    let mut hidden_count = 0;
+    let revealed = code
+        .split_inclusive('\n')
+        .map(|line| {
+            let content = line.trim_end_matches(['\r', '\n']);
+            let newline = &line[content.len()..];
+            let rest = content.trim_start();
+            let indent = &content[..content.len() - rest.len()];
+
+            let hidden_code = match rest.strip_prefix('#') {
+                Some("") => "",
+                Some(hidden_code) if hidden_code.starts_with(' ') => &hidden_code[1..],
+                _ => return line.to_string(),
+            };
+            hidden_count += 1;
+            if hidden_code.is_empty() {
+                format!("{indent}{HIDDEN_MARKER}{newline}")
+            } else {
+                format!("{indent}{hidden_code} {HIDDEN_MARKER}{newline}")
+            }
+        })
+        .collect();
+
+    (revealed, hidden_count)
+}
+
+/// Revert `reveal_hidden_lines`, returns None if some hidden lines were lost while formatting
+fn hide_revealed_lines(code: &str, hidden_count: usize) -> Option<String> {
+    let mut found_count = 0;
+    let hidden: String = code
+        .split_inclusive('\n')
+        .map(|line| {
+            let content = line.trim_end_matches(['\r', '\n']);
+            let newline = &line[content.len()..];
+            let Some(hidden_code) = content.strip_suffix(HIDDEN_MARKER) else {
+                return line.to_string();
+            };
+            found_count += 1;
+
+            let hidden_code = hidden_code.trim_end();
+            let rest = hidden_code.trim_start();
+            let indent = &hidden_code[..hidden_code.len() - rest.len()];
+            if rest.is_empty() {
+                format!("{indent}#{newline}")
+            } else {
+                format!("{indent}# {rest}{newline}")
+            }
+        })
+        .collect();
+
+    (found_count == hidden_count).then_some(hidden)
+}
+
+fn format_snippet(code: &str, options: &FormatOptions) -> Option<String> {
+    if let Ok(formatted) = fmt_file(code, None, options) {
+        return Some(formatted);
+    }
+    if !code.ends_with('\n') {
+        return None;
+    }
+
+    // statements or expressions only, not a full file
+    let wrapped = format!("{SNIPPET_START}{code}{SNIPPET_END}");
+    fmt_file(&wrapped, None, options)
+        .ok()?
+        .strip_prefix(SNIPPET_START)?
+        .strip_suffix(SNIPPET_END)
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::testing::*;
+
+    test_markdown!(
+        markdown_hidden_lines,
+        r#"```rust
+# use maud::html;
+# fn main() {
+let markup = html!{p{"Hello"}};
+#
+# }
+```
+"#,
+        r#"```rust
+# use maud::html;
+# fn main() {
+let markup = html! {
+    p { "Hello" }
+};
+#
+# }
+```
+"#
+    );
+
+    test_markdown!(
+        markdown_rust_code_block,
+        r#"# Components
+
+Some prose with `html!{p{"inline code"}}`.
+
+```rust
+fn footer() -> Markup {
+    html!{footer{a href="rss.atom"{"RSS Feed"}}}
+}
+```
+"#,
+        r#"# Components
+
+Some prose with `html!{p{"inline code"}}`.
+
+```rust
+fn footer() -> Markup {
+    html! {
+        footer {
+            a href="rss.atom" { "RSS Feed" }
+        }
+    }
+}
+```
+"#
+    );
+
+    test_markdown!(
+        markdown_snippet_code_block,
+        r#"```rust,ignore
+let name = "world";
+let markup = html!{p{"Hello " (name)}};
+```
+"#,
+        r#"```rust,ignore
+let name = "world";
+let markup = html! {
+    p { "Hello " (name) }
+};
+```
+"#
+    );
+
+    test_markdown!(
+        markdown_other_code_blocks_untouched,
+        r#"```
+html!{p{"no language"}}
+```
+
+~~~toml
+html = "{p{}}"
+~~~
+
+```rust
+html!{p{"not valid rust" }
+```
+"#,
+        r#"```
+html!{p{"no language"}}
+```
+
+~~~toml
+html = "{p{}}"
+~~~
+
+```rust
+html!{p{"not valid rust" }
+```
+"#
+    );
+
+    test_markdown!(
+        markdown_indented_code_block,
+        r#"- a list item:
+
+  ````rs
+  html!{p{"indented"}}
+  ````
+"#,
+        r#"- a list item:
+
+  ````rs
+  html! {
+      p { "indented" }
+  }
+  ````
+"#
+    );
+}
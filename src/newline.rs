@@ -0,0 +1,128 @@
+use crate::format::NewlineStyle;
+
+const BOM: char = '\u{feff}';
+
+/// Encoding details of a source file that the formatter doesn't see: the formatter works on
+/// `\n` line endings without a BOM, and they are restored with [`SourceStyle::restore`].
+#[derive(Debug, PartialEq)]
+pub struct SourceStyle {
+    bom: bool,
+    crlf: bool,
+    final_newline: bool,
+}
+
+impl SourceStyle {
+    /// Returns the style of `source` and its normalized content
+    pub fn normalize(source: &str, newline_style: NewlineStyle) -> (SourceStyle, String) {
+        let (bom, source) = match source.strip_prefix(BOM) {
+            Some(source) => (true, source),
+            None => (false, source),
+        };
+        let crlf = match newline_style {
+            // like rustfmt, the first line ending of the file wins
+            NewlineStyle::Auto => source
+                .find('\n')
+                .is_some_and(|idx| source[..idx].ends_with('\r')),
+            NewlineStyle::Unix => false,
+            NewlineStyle::Windows => true,
+            NewlineStyle::Native => cfg!(windows),
+        };
+        let style = SourceStyle {
+            bom,
+            crlf,
+            final_newline: source.ends_with('\n'),
+        };
+
+        (style, source.replace("\r\n", "\n"))
+    }
+
+    /// Applies the style back to `formatted`, the output of the formatter for the normalized source
+    pub fn restore(&self, formatted: String) -> String {
+        let mut formatted = match (self.final_newline, formatted.ends_with('\n')) {
+            (true, false) => formatted + "\n",
+            (false, true) => formatted.trim_end_matches('\n').to_string(),
+            _ => formatted,
+        };
+        if self.crlf {
+            formatted = formatted.replace('\n', "\r\n");
+        }
+        if self.bom {
+            formatted.insert(0, BOM);
+        }
+        formatted
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{format::FormatOptions, try_fmt_file, try_fmt_fragment, try_fmt_markdown};
+
+    fn options(newline_style: NewlineStyle) -> FormatOptions {
+        FormatOptions {
+            newline_style,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn keep_crlf_line_endings() {
+        let source = "fn main() {\r\n    html!{p{\"a\"}}\r\n}\r\n";
+        let expected = "fn main() {\r\n    html! {\r\n        p { \"a\" }\r\n    }\r\n}\r\n";
+
+        let formatted = try_fmt_file(source, &FormatOptions::default()).unwrap();
+
+        pretty_assertions::assert_eq!(formatted, expected);
+    }
+
+    #[test]
+    fn keep_crlf_line_endings_with_ignored_lines() {
+        let source = "html! {\r\n    p{\"a\"}\r\n    // maudfmt-ignore\r\n    p  {\"b\"}\r\n}\r\n";
+        let expected =
+            "html! {\r\n    p { \"a\" }\r\n    // maudfmt-ignore\r\n    p  {\"b\"}\r\n}\r\n";
+
+        let formatted = try_fmt_file(source, &FormatOptions::default()).unwrap();
+
+        pretty_assertions::assert_eq!(formatted, expected);
+    }
+
+    #[test]
+    fn keep_bom_and_missing_final_newline() {
+        let source = "\u{feff}fn main() {\n    html!{p{\"a\"}}\n}";
+        let expected = "\u{feff}fn main() {\n    html! {\n        p { \"a\" }\n    }\n}";
+
+        let formatted = try_fmt_file(source, &FormatOptions::default()).unwrap();
+
+        pretty_assertions::assert_eq!(formatted, expected);
+    }
+
+    #[test]
+    fn forced_newline_style() {
+        let source = "html!{p{\"a\"}}\r\n";
+
+        pretty_assertions::assert_eq!(
+            try_fmt_file(source, &options(NewlineStyle::Unix)).unwrap(),
+            "html! {\n    p { \"a\" }\n}\n"
+        );
+        pretty_assertions::assert_eq!(
+            try_fmt_file("html!{p{\"a\"}}\n", &options(NewlineStyle::Windows)).unwrap(),
+            "html! {\r\n    p { \"a\" }\r\n}\r\n"
+        );
+    }
+
+    #[test]
+    fn keep_crlf_in_fragments_and_markdown() {
+        pretty_assertions::assert_eq!(
+            try_fmt_fragment("p{\"a\"}\r\nbr;\r\n", 0, &FormatOptions::default()).unwrap(),
+            "p { \"a\" }\r\nbr;\r\n"
+        );
+        pretty_assertions::assert_eq!(
+            try_fmt_markdown(
+                "# Title\r\n\r\n```rust\r\nhtml!{p{\"a\"}}\r\n```\r\n",
+                &FormatOptions::default()
+            )
+            .unwrap(),
+            "# Title\r\n\r\n```rust\r\nhtml! {\r\n    p { \"a\" }\r\n}\r\n```\r\n"
+        );
+    }
+}
@@ -1,67 +1,51 @@
 use syn::spanned::Spanned as _;
 
 use crate::{
-    line_length::block_len,
-    print::Printer,
+    print::{Printer, directive::Layout, markups_must_expand},
     vendor::ast::{Block, Element},
 };
 
-impl<'a, 'b> Printer<'a, 'b> {
-    pub fn print_block<E: Into<Element>>(&mut self, block: Block<E>, indent_level: usize) {
-        self.print_inline_comment_and_whitespace(
-            block.brace_token.span.span().start(),
-            indent_level,
-            true,
-        );
+impl<'a> Printer<'a> {
+    pub fn print_block<E: Into<Element>>(
+        &mut self,
+        block: Block<E>,
+        indent_level: usize,
+        layout: Option<Layout>,
+    ) {
+        let brace_span = block.brace_token.span;
+        self.print_inline_comment_and_whitespace(brace_span.span().start(), true);
 
-        let expand = self.block_contains_comments(block.brace_token.span) || {
-            if let Some(blk_len) = block_len(&block) {
-                (self.line_len() + blk_len) > self.options.line_length
-            } else {
-                true
-            }
-        };
-        if block.markups.markups.is_empty() && !self.block_contains_comments(block.brace_token.span)
-        {
+        // a comment after the closing brace of a multi-line block keeps the block expanded
+        let contains_comments = self
+            .contains_comments(brace_span.open().start(), brace_span.close().end())
+            || (brace_span.open().start().line != brace_span.close().start().line
+                && self.attr_comment(brace_span.close().end()).is_some());
+        if block.markups.markups.is_empty() && !contains_comments {
             self.write("{}");
-            self.print_attr_comment(block.brace_token.span.close().span().end());
-        } else if !expand {
-            self.write("{");
-            if self.print_attr_comment(block.brace_token.span.open().span().end()) {
-                // expand if comment
-                self.new_line(indent_level + 1);
-                for markup in block.markups.markups {
-                    // there should be only one value
-                    self.print_markup(markup, indent_level + 1, true);
-                }
-                self.new_line(indent_level);
-                self.write("}");
-            } else {
-                for markup in block.markups.markups {
-                    self.write(" ");
-                    self.print_markup(markup, indent_level, false);
-                }
-                self.write(" }");
-            }
-            self.print_attr_comment(block.brace_token.span.close().span().end());
-        } else {
-            self.write("{");
-            self.print_attr_comment(block.brace_token.span.open().span().end());
+            self.print_attr_comment(brace_span.close().span().end());
+            return;
+        }
 
-            if block.markups.markups.is_empty() {
-                // Handle empty block with comments
-                self.print_block_comments(block.brace_token.span, indent_level + 1);
-            } else {
-                for markup in block.markups.markups {
-                    self.new_line(indent_level + 1);
-                    self.print_markup(markup, indent_level + 1, true);
+        // comments can't be collapsed, whatever the layout directive says
+        let layout = if contains_comments || self.attr_comment(brace_span.open().end()).is_some() {
+            Some(Layout::Expand)
+        } else {
+            layout.or(markups_must_expand(&block.markups).then_some(Layout::Expand))
+        };
+        self.group(layout, |p| {
+            p.write("{");
+            p.print_attr_comment(brace_span.open().end());
+            p.indent(|p| {
+                for (idx, markup) in block.markups.markups.into_iter().enumerate() {
+                    p.line();
+                    // no blank line right after the opening brace
+                    p.print_markup(markup, indent_level + 1, idx > 0);
                 }
-                self.print_trailing_comments(block.brace_token.span, indent_level + 1);
-            }
-
-            self.new_line(indent_level);
-            self.write("}");
-            self.print_attr_comment(block.brace_token.span.close().span().end());
-        }
+                p.print_comments_before(brace_span.close().start());
+            });
+            p.line();
+            p.write("}");
+        });
+        self.print_attr_comment(brace_span.close().end());
     }
 }
@@ -1,187 +1,120 @@
-use crop::RopeSlice;
-use proc_macro2::{LineColumn, extra::DelimSpan};
-use syn::spanned::Spanned as _;
+use proc_macro2::LineColumn;
 
-use crate::{format::line_column_to_byte, print::Printer};
+use crate::{
+    print::{Printer, doc::Doc},
+    trivia::{Comment, Trivia},
+};
 
-impl<'a, 'b> Printer<'a, 'b> {
+impl<'a> Printer<'a> {
     // Returns true if a comment was inserted
     pub fn print_attr_comment(&mut self, loc: LineColumn) -> bool {
-        if !self.is_trailing(loc) {
-            return false;
-        }
-
-        let token_end_byte = line_column_to_byte(self.source, loc);
-        let next_line_start_byte = self.source.byte_of_line(loc.line);
-
-        if let Some(comment) = self
-            .source
-            .byte_slice(token_end_byte..next_line_start_byte)
-            .to_string()
-            .split_once("//")
-            .map(|(_, txt)| txt)
-            .map(str::trim_end)
-            .map(str::to_string)
-        {
-            self.write("  ");
-            self.write_comment_text(&comment);
-            return true;
+        match self.attr_comment(loc) {
+            Some(comment) => {
+                self.doc
+                    .push(Doc::LineSuffix(format!("  {}", comment_text(comment))));
+                true
+            }
+            None => false,
         }
+    }
 
-        false
+    /// Comment at the end of the line of the token ending at `loc`
+    pub fn attr_comment(&self, loc: LineColumn) -> Option<&'a Comment> {
+        self.trivia.trailing(loc)
     }
 
+    /// Print the comments and the blank line above the token starting at `loc`, and the block
+    /// comments before it on its line
     pub fn print_inline_comment_and_whitespace(
         &mut self,
         loc: LineColumn,
-        indent_level: usize,
         preserve_blank_lines: bool,
     ) {
-        let mut cursor_line = loc.line - 1;
-        if cursor_line == 0 || !self.is_leading(loc) {
-            return;
-        }
-
-        if preserve_blank_lines
-            && self
-                .source
-                .line(cursor_line - 1)
-                .to_string()
-                .trim()
-                .is_empty()
-        {
-            self.buf = String::new();
-            self.new_line(indent_level);
+        let Some(leading) = self.trivia.leading(loc) else {
             return;
+        };
+
+        // blank lines above comments are kept, even when they are dropped above the token, and
+        // the one above a stripped directive stays where the directive was
+        let mut after_comment = false;
+        let mut blank_line = false;
+        for trivia in &leading.trivia {
+            match trivia {
+                Trivia::BlankLines(_) => blank_line = true,
+                Trivia::Comment(comment) => {
+                    if blank_line {
+                        self.blank_line();
+                    }
+                    blank_line = false;
+                    if !self.is_stripped_comment(comment) {
+                        self.print_comment(comment);
+                        self.hard_line();
+                    }
+                    after_comment = true;
+                }
+            }
         }
-
-        let mut comments = Vec::new();
-
-        while let Some(comment) = extract_inline_comment(self.source.line(cursor_line - 1)) {
-            comments.push(comment);
-            cursor_line -= 1;
+        if blank_line && (preserve_blank_lines || after_comment) {
+            self.blank_line();
         }
 
-        while let Some(comment) = comments.pop() {
-            self.write_comment_text(&comment);
-            self.new_line(indent_level);
+        for comment in &leading.inline {
+            self.print_comment(comment);
+            self.write(" ");
         }
     }
 
-    pub fn print_block_comments(&mut self, delim_span: DelimSpan, indent_level: usize) {
-        let start_line = delim_span.span().start().line - 1;
-        let end_line = delim_span.span().end().line - 1;
-
-        for line_idx in (start_line + 1)..end_line {
-            let line = self.source.line(line_idx);
-            if let Some((_, comment_part)) = line.to_string().split_once("//") {
-                self.write_comment_line(comment_part, indent_level);
+    /// Print the comments before the token starting at `loc`, a closing delimiter or the end of a
+    /// fragment, each on its own line
+    pub fn print_comments_before(&mut self, loc: LineColumn) {
+        let Some(leading) = self.trivia.leading(loc) else {
+            return;
+        };
+        let comments = leading.trivia.iter().filter_map(|trivia| match trivia {
+            Trivia::Comment(comment) => Some(comment),
+            Trivia::BlankLines(_) => None,
+        });
+        for comment in comments.chain(&leading.inline) {
+            if !self.is_stripped_comment(comment) {
+                self.hard_line();
+                self.print_comment(comment);
             }
         }
     }
 
-    pub fn block_contains_comments(&self, delim_span: DelimSpan) -> bool {
-        let start_line = delim_span.span().start().line - 1;
-        let end_line = delim_span.span().end().line - 1;
-
-        if start_line == end_line {
-            return false;
-        }
-
-        (start_line..=end_line).any(|line| {
-            self.source
-                .line(line)
-                .to_string()
-                .split_once("//")
-                .is_some()
+    /// Returns true if there are comments before the token starting at `loc`
+    pub fn has_leading_comments(&self, loc: LineColumn) -> bool {
+        self.trivia.leading(loc).is_some_and(|leading| {
+            !leading.inline.is_empty()
+                || leading.trivia.iter().any(
+                    |trivia| matches!(trivia, Trivia::Comment(comment) if !self.is_stripped_comment(comment)),
+                )
         })
     }
 
-    pub fn print_trailing_comments(&mut self, delim_span: DelimSpan, indent_level: usize) {
-        let start_line = delim_span.span().start().line - 1;
-        let end_line = delim_span.span().end().line - 1;
-
-        for line_idx in (start_line + 1)..end_line {
-            let line = self.source.line(line_idx);
-            let line_string = line.to_string();
-
-            if let Some((before_comment, comment_part)) = line_string.split_once("//")
-                && before_comment.trim().is_empty()
-            {
-                let has_content_after = ((line_idx + 1)..end_line).any(|later_line_idx| {
-                    let later_line = self.source.line(later_line_idx);
-                    let later_line_string = later_line.to_string();
-
-                    if let Some((before_comment, _)) = later_line_string.split_once("//") {
-                        !before_comment.trim().is_empty()
-                    } else {
-                        !later_line_string.trim().is_empty()
-                    }
-                });
-
-                if !has_content_after {
-                    self.write_comment_line(comment_part, indent_level);
-                }
-            }
-        }
+    /// Returns true if there are comments between `start` and `end`
+    pub fn contains_comments(&self, start: LineColumn, end: LineColumn) -> bool {
+        self.trivia
+            .comments_between(start, end)
+            .any(|comment| !self.is_stripped_comment(comment))
     }
 
-    fn write_comment_text(&mut self, comment: &str) {
-        self.write("//");
-        if !comment.is_empty() {
-            if !comment.starts_with(" ") {
-                self.write(" ");
+    /// The lines of a block comment are indented relative to the current line
+    fn print_comment(&mut self, comment: &Comment) {
+        for (idx, line) in comment_text(comment).split('\n').enumerate() {
+            if idx > 0 {
+                self.hard_line();
             }
-            self.write(comment);
+            self.write(line);
         }
     }
-
-    fn write_comment_line(&mut self, comment_part: &str, indent_level: usize) {
-        self.new_line(indent_level);
-        let comment = comment_part.trim_end();
-        self.write_comment_text(comment);
-    }
-
-    fn is_leading(&self, loc: LineColumn) -> bool {
-        let line_start_byte = self.source.byte_of_line(loc.line - 1);
-        let token_start_byte = line_column_to_byte(self.source, loc);
-
-        let before_token = self
-            .source
-            .byte_slice(line_start_byte..token_start_byte)
-            .to_string();
-
-        before_token.trim().is_empty()
-    }
-
-    fn is_trailing(&self, loc: LineColumn) -> bool {
-        let token_end_byte = line_column_to_byte(self.source, loc);
-        let next_line_start_byte = self.source.byte_of_line(loc.line);
-
-        let line_string = self
-            .source
-            .byte_slice(token_end_byte..next_line_start_byte)
-            .to_string();
-
-        line_string
-            .split_once("//")
-            .map(|(txt, _)| txt)
-            .unwrap_or(&line_string)
-            .trim()
-            .is_empty()
-    }
 }
 
-fn extract_inline_comment(line: RopeSlice) -> Option<String> {
-    let line_string = line.to_string();
-    if line_string.trim().starts_with("//") {
-        line_string
-            .split_once("//")
-            .map(|(_, txt)| txt)
-            .map(str::trim_end)
-            .map(str::to_string)
-    } else {
-        None
+/// Line comments get a space after the `//`
+fn comment_text(comment: &Comment) -> String {
+    match comment.line_text() {
+        Some(text) if !text.is_empty() && !text.starts_with(' ') => format!("// {text}"),
+        _ => comment.text.clone(),
     }
 }
 
@@ -383,6 +316,48 @@ mod test {
         "#
     );
 
+    test_default!(
+        comments_slashes_in_multi_line_block,
+        r#"
+        html! {
+            p {
+                "https://example.org"
+            }
+        }
+        "#,
+        r#"
+        html! {
+            p { "https://example.org" }
+        }
+        "#
+    );
+
+    test_default!(
+        comments_and_blank_lines_above_node,
+        r#"
+        html! {
+            p { "a" }
+            // above blank line
+
+            p { "b" }
+
+            // below blank line
+            p { "c" }
+        }
+        "#,
+        r#"
+        html! {
+            p { "a" }
+            // above blank line
+
+            p { "b" }
+
+            // below blank line
+            p { "c" }
+        }
+        "#
+    );
+
     test_default!(
         keep_indents_in_comments_attrs,
         r#"
@@ -596,10 +571,12 @@ mod test {
         html! {
             // before match
             @match value {
+                // comment in match
                 Some(x) => {
                     // comment in arm
                     span { (x) }
                 }
+                // comment between arms
                 None => {
                     "empty"
                     // trailing in arm
@@ -668,6 +645,7 @@ mod test {
             (complex_expression())  // inline on splice
             // after splice
             ({
+                // comment in block splice
                 let x = 5;
                 x + 1
             })
@@ -769,7 +747,9 @@ mod test {
                     li { (item) }  // inline in loop
                 }
                 // after loop
-            } @else {
+            }
+            // before else
+            @else {
                 // inside else
                 p { "No content" }
             }
@@ -795,4 +775,164 @@ mod test {
         }
         "#
     );
+
+    test_default!(
+        block_comments_around_markups,
+        r#"
+        html! {
+            /* leading */
+            p { "a" }  /* trailing */
+            "b" /* inline */ "c"
+            (splice) /* after splice */
+            br; /* after semi */
+            @let y = 1; /* after let */
+            /* before close */
+        }
+        "#,
+        r#"
+        html! {
+            /* leading */
+            p { "a" }  /* trailing */
+            "b"
+            /* inline */ "c"
+            (splice)  /* after splice */
+            br;  /* after semi */
+            @let y = 1;  /* after let */
+            /* before close */
+        }
+        "#
+    );
+
+    test_default!(
+        block_comments_in_blocks,
+        r#"
+        html! {
+            div {
+                /* inside */
+                "a"
+            }
+            span { /* empty */ }
+            { /* block markup */ "b" }
+            @if cond { "c" } /* after if */
+            @while cond {
+                "d"
+                /* in while */
+            }
+            @match x {
+                Some(y) => { /* arm */ (y) }
+                None => {}
+            }
+        }
+        "#,
+        r#"
+        html! {
+            div {
+                /* inside */
+                "a"
+            }
+            span {
+                /* empty */
+            }
+            {
+                /* block markup */ "b"
+            }
+            @if cond { "c" }  /* after if */
+            @while cond {
+                "d"
+                /* in while */
+            }
+            @match x {
+                Some(y) => {
+                    /* arm */ (y)
+                }
+                None => {}
+            }
+        }
+        "#
+    );
+
+    test_default!(
+        block_comments_in_attributes,
+        r#"
+        html! {
+            a /* name */ href="x" /* between */ title="y" { "a" }
+            p /* before class */ .a .b /* before block */ { "b" }
+            input.a[on] /* after toggler */ type="checkbox";
+        }
+        "#,
+        r#"
+        html! {
+            a /* name */ href="x" /* between */ title="y" { "a" }
+            p /* before class */ .a.b /* before block */ { "b" }
+            input.a[on] /* after toggler */ type="checkbox";
+        }
+        "#
+    );
+
+    test_default!(
+        block_comments_multi_line_reindented,
+        r#"
+        html! {
+                    /*
+                     * moved
+                     *   left
+                     */
+            section {
+        /* deep
+             indent */
+                "a" /* multi
+                       trailing */
+            }
+        }
+        "#,
+        r#"
+        html! {
+            /*
+             * moved
+             *   left
+             */
+            section {
+                /* deep
+                     indent */
+                "a"  /* multi
+                       trailing */
+            }
+        }
+        "#
+    );
+
+    test_fragment!(
+        block_comments_in_fragment,
+        "p{\"a\"} /* end */\n/* last */\n",
+        "p { \"a\" }  /* end */\n/* last */\n"
+    );
+
+    test_default!(
+        blank_line_after_brace_above_comment,
+        r#"
+        html! {
+            ul {
+
+                // first
+                li { "a" }
+            }
+            ul {
+
+                li { "b" }
+            }
+        }
+        "#,
+        r#"
+        html! {
+            ul {
+
+                // first
+                li { "a" }
+            }
+            ul {
+                li { "b" }
+            }
+        }
+        "#
+    );
 }
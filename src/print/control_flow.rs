@@ -1,30 +1,28 @@
-use syn::{Expr, spanned::Spanned as _};
+use quote::ToTokens;
+use syn::{BinOp, Expr, Stmt, spanned::Spanned as _};
 
 use crate::{
-    print::Printer,
-    unparse::{unparse_local, unparse_pat},
-    vendor::ast::{ControlFlow, ControlFlowKind, Element, IfExpr, IfOrBlock},
+    print::{Printer, directive::Layout},
+    vendor::ast::{ControlFlow, ControlFlowKind, Element, IfExpr, IfOrBlock, Markup},
 };
 
-impl<'a, 'b> Printer<'a, 'b> {
+impl<'a> Printer<'a> {
     pub fn print_control_flow<E: Into<Element>>(
         &mut self,
         control_flow: ControlFlow<E>,
         indent_level: usize,
     ) {
-        self.print_inline_comment_and_whitespace(
-            control_flow.at_token.span.span().start(),
-            indent_level,
-            true,
-        );
+        let start = control_flow.at_token.span.span().start();
+        let layout = self.layout_directives(start).body;
+        self.print_inline_comment_and_whitespace(start, true);
         match control_flow.kind {
             ControlFlowKind::If(if_expr) => {
                 self.write("@");
-                self.print_if_expr(if_expr, indent_level);
+                self.print_if_expr(if_expr, indent_level, layout);
             }
             ControlFlowKind::For(for_expr) => {
                 self.write("@for ");
-                self.write(&unparse_pat(&for_expr.pat, self.base_indent + indent_level).join("\n"));
+                self.write(&self.format_pat(&for_expr.pat, indent_level).join("\n"));
                 self.write(" in ");
                 // handle range separately, to avoid prettyplease adding unnecessary parentheses
                 match for_expr.expr {
@@ -36,49 +34,82 @@ impl<'a, 'b> Printer<'a, 'b> {
                     }
                 }
                 self.write(" ");
-                self.print_block(for_expr.body, indent_level);
+                self.print_block(for_expr.body, indent_level, layout);
+            }
+            ControlFlowKind::Let(local)
+                if self.contains_code_comments(
+                    local.span().start(),
+                    local.semi_token.span().end(),
+                ) =>
+            {
+                self.write("@");
+                let end = local.semi_token.span().end();
+                self.print_verbatim(local.span().start(), end, local.to_token_stream());
+                self.print_attr_comment(end);
             }
             ControlFlowKind::Let(local) => {
                 let let_indent_level = match indent_level {
                     0 => 0,
                     indent_level => indent_level - 1,
                 };
-                let unparsed_lines = unparse_local(&local, let_indent_level);
+                let semi_end = local.semi_token.span().end();
+                // written first, the code starts after it
                 self.write("@");
-                match unparsed_lines.len() {
+                let mut lines = self.format_stmts(&[Stmt::Local(local)], let_indent_level);
+                // the `;` is written after the lines
+                if let Some(last) = lines.last_mut()
+                    && let Some(stripped) = last.strip_suffix(';')
+                {
+                    *last = stripped.to_string();
+                }
+                match lines.len() {
                     0 => {}
-                    1 => {
-                        self.write(&unparsed_lines[0]);
-                    }
+                    1 => self.write(lines[0].trim()),
                     _ => {
-                        self.write(unparsed_lines[0].trim_start());
-
-                        for line in &unparsed_lines[1..] {
-                            self.new_line(0);
-                            self.write(line);
-                        }
+                        // the lines are already indented
+                        self.write(lines[0].trim_start());
+                        self.write("\n");
+                        self.write(&lines[1..].join("\n"));
                     }
                 }
                 self.write(";");
-                self.print_attr_comment(local.semi_token.span().end());
+                self.print_attr_comment(semi_end);
             }
             ControlFlowKind::Match(match_expr) => {
                 self.write("@match ");
                 self.print_expr(match_expr.expr, indent_level);
                 self.write(" {");
                 self.print_attr_comment(match_expr.brace_token.span.open().span().end());
-                for arm in match_expr.arms {
-                    self.new_line(indent_level + 1);
-                    self.write(&unparse_pat(&arm.pat, self.base_indent + indent_level).join("\n"));
-                    if let Some((_, guard_cond)) = arm.guard {
-                        self.write(" if ");
-                        self.print_expr(guard_cond, indent_level);
+                self.indent(|p| {
+                    for (idx, arm) in match_expr.arms.into_iter().enumerate() {
+                        p.hard_line();
+                        p.print_inline_comment_and_whitespace(arm.pat.span().start(), idx > 0);
+                        let (start, end) =
+                            (arm.pat.span().start(), arm.fat_arrow_token.span().end());
+                        if p.contains_code_comments(start, end) {
+                            let mut tokens = arm.pat.to_token_stream();
+                            if let Some((if_token, guard_cond)) = &arm.guard {
+                                if_token.to_tokens(&mut tokens);
+                                guard_cond.to_tokens(&mut tokens);
+                            }
+                            p.print_verbatim(start, end, tokens);
+                            p.write(" ");
+                        } else {
+                            p.write(&p.format_pat(&arm.pat, indent_level + 1).join("\n"));
+                            if let Some((_, guard_cond)) = arm.guard {
+                                p.write(" if ");
+                                p.print_expr(guard_cond, indent_level + 1);
+                            }
+                            p.write(" => ");
+                        }
+                        match arm.body {
+                            Markup::Block(block) => p.print_block(block, indent_level + 1, layout),
+                            body => p.print_markup(body, indent_level + 1, true),
+                        }
                     }
-                    self.write(" => ");
-                    self.print_markup(arm.body, indent_level + 1, true);
-                }
-                self.print_trailing_comments(match_expr.brace_token.span, indent_level + 1);
-                self.new_line(indent_level);
+                    p.print_comments_before(match_expr.brace_token.span.close().start());
+                });
+                self.hard_line();
                 self.write("}");
                 self.print_attr_comment(match_expr.brace_token.span.close().span().end());
             }
@@ -88,35 +119,47 @@ impl<'a, 'b> Printer<'a, 'b> {
                     Expr::Let(expr_let) => {
                         // crashes prettyplease > syn can't parse it
                         self.write("let ");
-                        self.write(
-                            &unparse_pat(&expr_let.pat, self.base_indent + indent_level).join("\n"),
-                        );
+                        self.write(&self.format_pat(&expr_let.pat, indent_level).join("\n"));
                         self.write(" = ");
                         self.print_expr(*expr_let.expr, indent_level);
                         self.write(" ");
                     }
+                    cond if is_let_chain(&cond) => self.print_let_chain(cond),
                     _ => {
                         // usual case
                         self.print_expr(while_expr.cond, indent_level);
                         self.write(" ");
                     }
                 }
-                self.print_block(while_expr.body, indent_level);
+                self.print_block(while_expr.body, indent_level, layout);
             }
         }
     }
 
-    fn print_if_expr<E: Into<Element>>(&mut self, if_expr: IfExpr<E>, indent_level: usize) {
+    /// prettyplease can't print `let` outside of a condition either, the chain is kept as written
+    fn print_let_chain(&mut self, cond: Expr) {
+        let span = cond.span();
+        self.print_verbatim(span.start(), span.end(), cond.to_token_stream());
+        self.write(" ");
+    }
+
+    fn print_if_expr<E: Into<Element>>(
+        &mut self,
+        if_expr: IfExpr<E>,
+        indent_level: usize,
+        layout: Option<Layout>,
+    ) {
         self.write("if ");
         match if_expr.cond {
             Expr::Let(expr_let) => {
                 // crashes prettyplease > syn can't parse it
                 self.write("let ");
-                self.write(&unparse_pat(&expr_let.pat, self.base_indent + indent_level).join("\n"));
+                self.write(&self.format_pat(&expr_let.pat, indent_level).join("\n"));
                 self.write(" = ");
                 self.print_expr(*expr_let.expr, indent_level);
                 self.write(" ");
             }
+            cond if is_let_chain(&cond) => self.print_let_chain(cond),
             _ => {
                 // usual case
                 self.print_expr(if_expr.cond, indent_level);
@@ -124,17 +167,25 @@ impl<'a, 'b> Printer<'a, 'b> {
             }
         }
 
-        self.print_block(if_expr.then_branch, indent_level);
+        self.print_block(if_expr.then_branch, indent_level, layout);
 
-        if let Some((_, _, if_or_block)) = if_expr.else_branch {
-            self.write(" @else ");
+        if let Some((at_token, _, if_or_block)) = if_expr.else_branch {
+            let at = at_token.span.start();
+            if self.has_leading_comments(at) {
+                // the comments go on their own lines, between the blocks
+                self.hard_line();
+                self.print_inline_comment_and_whitespace(at, false);
+                self.write("@else ");
+            } else {
+                self.write(" @else ");
+            }
 
             match *if_or_block {
                 IfOrBlock::If(else_if_expr) => {
-                    self.print_if_expr(else_if_expr, indent_level);
+                    self.print_if_expr(else_if_expr, indent_level, layout);
                 }
                 IfOrBlock::Block(block) => {
-                    self.print_block(block, indent_level);
+                    self.print_block(block, indent_level, layout);
                 }
             }
         }
@@ -154,6 +205,20 @@ impl<'a, 'b> Printer<'a, 'b> {
     }
 }
 
+/// `let` chained with other conditions by `&&`
+fn is_let_chain(cond: &Expr) -> bool {
+    match cond {
+        Expr::Binary(binary) if matches!(binary.op, BinOp::And(_)) => {
+            contains_let(&binary.left) || contains_let(&binary.right)
+        }
+        _ => false,
+    }
+}
+
+fn contains_let(cond: &Expr) -> bool {
+    matches!(cond, Expr::Let(_)) || is_let_chain(cond)
+}
+
 #[cfg(test)]
 mod test {
     use crate::testing::*;
@@ -449,4 +514,69 @@ mod test {
         }
         "##
     );
+
+    test_default!(
+        control_flow_with_comments_kept_verbatim,
+        r#"
+        html! {
+            @let x = {
+                    // computed
+                    1 + 2
+                };
+            @if a /* first */ && b {
+                "x"
+            }
+            @match v {
+                Some(y) if y > 0 // positive
+                    => { (y) }
+                _ => {}
+            }
+        }
+        "#,
+        r#"
+        html! {
+            @let x = {
+                    // computed
+                    1 + 2
+                };
+            @if a /* first */ && b { "x" }
+            @match v {
+                Some(y) if y > 0 // positive
+                    => { (y) }
+                _ => {}
+            }
+        }
+        "#
+    );
+
+    test_small_line!(
+        multi_line_patterns,
+        r#"
+        html! {
+            @for (first_name, second_name, third_name) in items { (first_name) }
+            @match value {
+                Some((first_name, second_name)) => { (first_name) }
+                _ => {}
+            }
+        }
+        "#,
+        r#"
+        html! {
+            @for (
+                first_name,
+                second_name,
+                third_name,
+            ) in items { (first_name) }
+            @match value {
+                Some(
+                    (
+                        first_name,
+                        second_name,
+                    ),
+                ) => { (first_name) }
+                _ => {}
+            }
+        }
+        "#
+    );
 }
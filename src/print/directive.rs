@@ -0,0 +1,313 @@
+use proc_macro2::LineColumn;
+
+use crate::{
+    print::Printer,
+    trivia::{Comment, Trivia},
+};
+
+const DIRECTIVE_PREFIX: &str = "maudfmt:";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    Expand,
+    Collapse,
+}
+
+/// Layout forced by `// maudfmt: ...` comments placed on the lines right above a node.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LayoutDirectives {
+    /// `expand` / `collapse`: element block, control flow blocks or match arms
+    pub body: Option<Layout>,
+    /// `expand-attrs` / `collapse-attrs`: element attribute list
+    pub attrs: Option<Layout>,
+}
+
+impl<'a> Printer<'a> {
+    pub fn layout_directives(&self, loc: LineColumn) -> LayoutDirectives {
+        let mut directives = LayoutDirectives::default();
+        let Some(leading) = self
+            .trivia
+            .leading(loc)
+            .filter(|leading| leading.starts_line)
+        else {
+            return directives;
+        };
+
+        // the closest directive wins, only the comments right above the node count
+        for trivia in leading.trivia.iter().rev() {
+            let Trivia::Comment(comment) = trivia else {
+                break;
+            };
+            let Some(text) = comment.line_text() else {
+                continue;
+            };
+            match parse_directive(text) {
+                Some(Directive::Body(layout)) => {
+                    directives.body.get_or_insert(layout);
+                }
+                Some(Directive::Attrs(layout)) => {
+                    directives.attrs.get_or_insert(layout);
+                }
+                None => {}
+            }
+        }
+
+        directives
+    }
+
+    /// Returns true if the comment is a directive that should not be printed
+    pub fn is_stripped_comment(&self, comment: &Comment) -> bool {
+        self.options.strip_layout_directives && comment.line_text().is_some_and(is_layout_directive)
+    }
+}
+
+/// `comment` is the comment text, without the leading `//`
+pub fn is_layout_directive(comment: &str) -> bool {
+    parse_directive(comment).is_some()
+}
+
+enum Directive {
+    Body(Layout),
+    Attrs(Layout),
+}
+
+// `comment` is the comment text, without the leading `//`
+fn parse_directive(comment: &str) -> Option<Directive> {
+    match comment.trim().strip_prefix(DIRECTIVE_PREFIX)?.trim() {
+        "expand" => Some(Directive::Body(Layout::Expand)),
+        "collapse" => Some(Directive::Body(Layout::Collapse)),
+        "expand-attrs" => Some(Directive::Attrs(Layout::Expand)),
+        "collapse-attrs" => Some(Directive::Attrs(Layout::Collapse)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::testing::*;
+
+    test_default!(
+        directive_expand_block,
+        r#"
+        html! {
+            // maudfmt: expand
+            p { "Small text" }
+        }
+        "#,
+        r#"
+        html! {
+            // maudfmt: expand
+            p {
+                "Small text"
+            }
+        }
+        "#
+    );
+
+    test_small_line!(
+        directive_collapse_block,
+        r#"
+        html! {
+            // maudfmt: collapse
+            p { "Some text" (value) "longer than the line" }
+        }
+        "#,
+        r#"
+        html! {
+            // maudfmt: collapse
+            p { "Some text" (value) "longer than the line" }
+        }
+        "#
+    );
+
+    test_default!(
+        directive_collapse_nested_elements,
+        r#"
+        html! {
+            // maudfmt: collapse
+            li {
+                a href="/" { "Home" }
+            }
+        }
+        "#,
+        r#"
+        html! {
+            // maudfmt: collapse
+            li { a href="/" { "Home" } }
+        }
+        "#
+    );
+
+    test_default!(
+        directive_collapse_keeps_comments,
+        r#"
+        html! {
+            // maudfmt: collapse
+            p {
+                // a comment always expands the block
+                "text"
+            }
+        }
+        "#,
+        r#"
+        html! {
+            // maudfmt: collapse
+            p {
+                // a comment always expands the block
+                "text"
+            }
+        }
+        "#
+    );
+
+    test_default!(
+        directive_expand_attrs,
+        r#"
+        html! {
+            // maudfmt: expand-attrs
+            a href="/" class="link" { "Home" }
+        }
+        "#,
+        r#"
+        html! {
+            // maudfmt: expand-attrs
+            a
+                href="/"
+                class="link"
+            { "Home" }
+        }
+        "#
+    );
+
+    test_small_line!(
+        directive_collapse_attrs,
+        r#"
+        html! {
+            // maudfmt: collapse-attrs
+            a href="/index.html" class="link" {
+                "Home"
+            }
+        }
+        "#,
+        r#"
+        html! {
+            // maudfmt: collapse-attrs
+            a href="/index.html" class="link" {
+                "Home"
+            }
+        }
+        "#
+    );
+
+    test_default!(
+        directive_expand_and_collapse_attrs,
+        r#"
+        html! {
+            // maudfmt: collapse-attrs
+            // maudfmt: expand
+            a href="/" { "Home" }
+        }
+        "#,
+        r#"
+        html! {
+            // maudfmt: collapse-attrs
+            // maudfmt: expand
+            a href="/" {
+                "Home"
+            }
+        }
+        "#
+    );
+
+    test_default!(
+        directive_expand_if,
+        r#"
+        html! {
+            // maudfmt: expand
+            @if user.is_admin() { "admin" } @else { "user" }
+        }
+        "#,
+        r#"
+        html! {
+            // maudfmt: expand
+            @if user.is_admin() {
+                "admin"
+            } @else {
+                "user"
+            }
+        }
+        "#
+    );
+
+    test_small_line!(
+        directive_collapse_for,
+        r#"
+        html! {
+            // maudfmt: collapse
+            @for item in items {
+                li { (item) }
+            }
+        }
+        "#,
+        r#"
+        html! {
+            // maudfmt: collapse
+            @for item in items { li { (item) } }
+        }
+        "#
+    );
+
+    test_default!(
+        directive_match_arms,
+        r#"
+        html! {
+            // maudfmt: expand
+            @match value {
+                Some(x) => { (x) }
+                None => { "empty" }
+            }
+            // maudfmt: collapse
+            @match value {
+                Some(x) => {
+                    (x)
+                }
+                None => "empty"
+            }
+        }
+        "#,
+        r#"
+        html! {
+            // maudfmt: expand
+            @match value {
+                Some(x) => {
+                    (x)
+                }
+                None => {
+                    "empty"
+                }
+            }
+            // maudfmt: collapse
+            @match value {
+                Some(x) => { (x) }
+                None => "empty"
+            }
+        }
+        "#
+    );
+
+    test_default!(
+        directive_unknown_is_plain_comment,
+        r#"
+        html! {
+            // maudfmt: squash
+            p { "text" }
+        }
+        "#,
+        r#"
+        html! {
+            // maudfmt: squash
+            p { "text" }
+        }
+        "#
+    );
+}
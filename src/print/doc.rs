@@ -0,0 +1,282 @@
+//! Layout-independent document built by the printer, then laid out by [`render`]
+//! following Wadler's "prettier printer": a group is printed on a single line when it fits.
+
+use crate::{line_length::display_width, print::directive::Layout};
+
+const INDENT: &str = "    ";
+
+#[derive(Debug, Clone)]
+pub enum Doc {
+    /// Printed as is, multi-line literals and rust code may contain line breaks
+    Text(String),
+    /// A space, or a line break when the enclosing group is expanded
+    Line,
+    /// Nothing, or a line break when the enclosing group is expanded
+    SoftLine,
+    /// Always a line break
+    HardLine,
+    /// Printed at the end of the current line, for trailing comments. Its next lines are indented
+    /// like the current line.
+    LineSuffix(String),
+    /// Line breaks inside are indented one more level
+    Indent(Vec<Doc>),
+    /// Printed on a single line if it fits, unless a layout is forced
+    Group {
+        layout: Option<Layout>,
+        contents: Vec<Doc>,
+    },
+    /// Depends on whether the enclosing group is expanded
+    IfBreak { broken: Vec<Doc>, flat: Vec<Doc> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+/// Lay out `docs` for lines of `line_length` columns, starting at `column` of a line indented
+/// by `base_indent` levels.
+pub fn render(docs: &[Doc], base_indent: usize, column: usize, line_length: usize) -> String {
+    let mut renderer = Renderer {
+        out: String::new(),
+        column,
+        line_indent: base_indent,
+        pending_indent: None,
+        line_suffix: String::new(),
+        line_length,
+    };
+    renderer.render(docs, base_indent);
+    renderer.out
+}
+
+struct Renderer {
+    out: String,
+    column: usize,
+    line_indent: usize,
+    /// indentation of the new line, written with its first text to avoid trailing whitespace
+    pending_indent: Option<usize>,
+    line_suffix: String,
+    line_length: usize,
+}
+
+impl Renderer {
+    fn render(&mut self, docs: &[Doc], base_indent: usize) {
+        let mut stack: Vec<(usize, Mode, &Doc)> = docs
+            .iter()
+            .rev()
+            .map(|doc| (base_indent, Mode::Break, doc))
+            .collect();
+
+        while let Some((indent, mode, doc)) = stack.pop() {
+            match doc {
+                Doc::Text(text) => self.text(text),
+                Doc::Line if mode == Mode::Flat => self.text(" "),
+                Doc::SoftLine if mode == Mode::Flat => {}
+                Doc::Line | Doc::SoftLine | Doc::HardLine => self.new_line(indent),
+                Doc::LineSuffix(suffix) => self.line_suffix += suffix,
+                Doc::Indent(docs) => {
+                    stack.extend(docs.iter().rev().map(|doc| (indent + 1, mode, doc)));
+                }
+                Doc::Group { layout, contents } => {
+                    let mode = match (mode, layout) {
+                        (Mode::Flat, _) => Mode::Flat,
+                        (Mode::Break, Some(Layout::Expand)) => Mode::Break,
+                        (Mode::Break, Some(Layout::Collapse)) => Mode::Flat,
+                        (Mode::Break, None) if self.fits(contents, &stack) => Mode::Flat,
+                        (Mode::Break, None) => Mode::Break,
+                    };
+                    stack.extend(contents.iter().rev().map(|doc| (indent, mode, doc)));
+                }
+                Doc::IfBreak { broken, flat } => {
+                    let docs = if mode == Mode::Break { broken } else { flat };
+                    stack.extend(docs.iter().rev().map(|doc| (indent, mode, doc)));
+                }
+            }
+        }
+        self.flush_line_suffix();
+    }
+
+    /// Returns true if `contents` fits flat on the current line, with what follows it until the
+    /// next possible line break.
+    fn fits(&self, contents: &[Doc], rest: &[(usize, Mode, &Doc)]) -> bool {
+        let mut width = self.line_length as isize - self.column as isize;
+        let mut docs: Vec<(Mode, &Doc)> =
+            contents.iter().rev().map(|doc| (Mode::Flat, doc)).collect();
+        let mut rest = rest.iter().rev().map(|&(_, mode, doc)| (mode, doc));
+
+        while width >= 0 {
+            let Some((mode, doc)) = docs.pop().or_else(|| rest.next()) else {
+                return true;
+            };
+            match doc {
+                Doc::Text(text) => match text.split_once('\n') {
+                    Some((first_line, _)) => {
+                        return mode == Mode::Break && display_width(first_line) as isize <= width;
+                    }
+                    None => width -= display_width(text) as isize,
+                },
+                Doc::Line | Doc::SoftLine if mode == Mode::Break => return true,
+                Doc::Line => width -= 1,
+                Doc::SoftLine => {}
+                // a line break or a comment can't be printed flat
+                Doc::HardLine | Doc::LineSuffix(_) => return mode == Mode::Break,
+                Doc::Indent(contents) => docs.extend(contents.iter().rev().map(|doc| (mode, doc))),
+                Doc::Group { layout, contents } => {
+                    // what follows the group is measured up to its first possible line break
+                    if layout == &Some(Layout::Expand) && mode == Mode::Flat {
+                        return false;
+                    }
+                    docs.extend(contents.iter().rev().map(|doc| (mode, doc)));
+                }
+                Doc::IfBreak { broken, flat } => {
+                    let contents = if mode == Mode::Break { broken } else { flat };
+                    docs.extend(contents.iter().rev().map(|doc| (mode, doc)));
+                }
+            }
+        }
+        false
+    }
+
+    fn text(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        if let Some(indent) = self.pending_indent.take() {
+            self.out += &INDENT.repeat(indent);
+        }
+        self.out += text;
+        self.column = match text.rsplit_once('\n') {
+            Some((_, last_line)) => display_width(last_line),
+            None => self.column + display_width(text),
+        };
+    }
+
+    fn new_line(&mut self, indent: usize) {
+        self.flush_line_suffix();
+        self.out.push('\n');
+        self.line_indent = indent;
+        self.pending_indent = Some(indent);
+        self.column = indent * INDENT.len();
+    }
+
+    fn flush_line_suffix(&mut self) {
+        let suffix = std::mem::take(&mut self.line_suffix);
+        for (idx, line) in suffix.split('\n').enumerate() {
+            if idx > 0 {
+                self.new_line(self.line_indent);
+            }
+            self.text(line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn text(text: &str) -> Doc {
+        Doc::Text(text.to_string())
+    }
+
+    fn block(layout: Option<Layout>, items: &[&str]) -> Doc {
+        let mut inner = Vec::new();
+        for item in items {
+            inner.push(Doc::Line);
+            inner.push(text(item));
+        }
+        Doc::Group {
+            layout,
+            contents: vec![text("{"), Doc::Indent(inner), Doc::Line, text("}")],
+        }
+    }
+
+    #[test]
+    fn group_fits() {
+        let docs = [text("p "), block(None, &["\"a\"", "\"b\""])];
+
+        assert_eq!(render(&docs, 0, 0, 20), "p { \"a\" \"b\" }");
+        assert_eq!(render(&docs, 0, 0, 10), "p {\n    \"a\"\n    \"b\"\n}");
+        assert_eq!(
+            render(&docs, 1, 4, 16),
+            "p {\n        \"a\"\n        \"b\"\n    }"
+        );
+    }
+
+    #[test]
+    fn forced_layout() {
+        let docs = [block(Some(Layout::Expand), &["a"])];
+        assert_eq!(render(&docs, 0, 0, 100), "{\n    a\n}");
+
+        let docs = [block(Some(Layout::Collapse), &["a", "b"])];
+        assert_eq!(render(&docs, 0, 0, 2), "{ a b }");
+    }
+
+    #[test]
+    fn expanded_group_breaks_parent() {
+        let docs = [Doc::Group {
+            layout: None,
+            contents: vec![
+                text("{"),
+                Doc::Indent(vec![Doc::Line, block(Some(Layout::Expand), &["a"])]),
+                Doc::Line,
+                text("}"),
+            ],
+        }];
+
+        assert_eq!(render(&docs, 0, 0, 100), "{\n    {\n        a\n    }\n}");
+    }
+
+    #[test]
+    fn rest_of_line_counts() {
+        // the group fits, but not with the text following it
+        let docs = [block(None, &["a"]), text(" @else"), Doc::HardLine];
+
+        assert_eq!(render(&docs, 0, 0, 11), "{ a } @else\n");
+        assert_eq!(render(&docs, 0, 0, 10), "{\n    a\n} @else\n");
+    }
+
+    #[test]
+    fn line_suffix_at_end_of_line() {
+        let docs = [
+            text("a"),
+            Doc::LineSuffix(String::from("  // comment")),
+            text(";"),
+            Doc::HardLine,
+            text("b"),
+        ];
+
+        assert_eq!(render(&docs, 0, 0, 100), "a;  // comment\nb");
+    }
+
+    #[test]
+    fn if_break_and_soft_line() {
+        let docs = [Doc::Group {
+            layout: None,
+            contents: vec![
+                text("a"),
+                Doc::Indent(vec![Doc::SoftLine, text(".b")]),
+                Doc::IfBreak {
+                    broken: vec![Doc::HardLine],
+                    flat: vec![text(" ")],
+                },
+                text("{"),
+            ],
+        }];
+
+        assert_eq!(render(&docs, 0, 0, 10), "a.b {");
+        assert_eq!(render(&docs, 0, 0, 4), "a\n    .b\n{");
+    }
+
+    #[test]
+    fn no_trailing_whitespace_on_blank_lines() {
+        let docs = [Doc::Indent(vec![
+            text("a"),
+            Doc::HardLine,
+            Doc::HardLine,
+            text("b"),
+        ])];
+
+        assert_eq!(render(&docs, 0, 0, 100), "a\n\n    b");
+    }
+}
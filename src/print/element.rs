@@ -1,41 +1,36 @@
-use quote::quote;
+use quote::ToTokens as _;
 use syn::{
     spanned::Spanned as _,
     token::{Dot, Pound},
 };
 
 use crate::{
-    line_length::{block_len, element_attrs_len},
     print::Printer,
+    unparse::{unparse_html_attribute_name, unparse_html_name},
     vendor::ast::{
-        Attribute, AttributeType, Element, ElementBody, HtmlName, HtmlNameFragment,
-        HtmlNameOrMarkup, HtmlNamePunct, Toggler,
+        Attribute, AttributeType, Element, ElementBody, HtmlName, HtmlNameOrMarkup, Toggler,
     },
 };
 
-impl<'a, 'b> Printer<'a, 'b> {
+impl<'a> Printer<'a> {
     pub fn print_element_with_contents(
         &mut self,
         Element { name, attrs, body }: Element,
         indent_level: usize,
         preserve_blank_lines: bool,
     ) {
-        // Check if this element's block will be collapsed
-        let will_collapse_block = match &body {
-            ElementBody::Block(block) => {
-                !self.block_contains_comments(block.brace_token.span) && {
-                    if let Some(blk_len) = block_len(block) {
-                        (self.line_len() + blk_len) <= self.options.line_length
-                    } else {
-                        false
-                    }
-                }
-            }
-            _ => false,
+        let start = match (&name, attrs.first()) {
+            (Some(html_name), _) => Some(html_name.span().start()),
+            (None, Some(attr)) => Some(attr.span().start()),
+            (None, None) => None,
         };
+        let directives = start
+            .map(|loc| self.layout_directives(loc))
+            .unwrap_or_default();
+        if let Some(start) = start {
+            self.print_inline_comment_and_whitespace(start, preserve_blank_lines);
+        }
 
-        // Don't preserve blank lines if this element's block will be collapsed
-        let preserve_blank_lines = preserve_blank_lines && !will_collapse_block;
         // sorting out attributes
         let mut id_name: Option<(Pound, HtmlNameOrMarkup)> = None;
         let mut classes: Vec<(Dot, HtmlNameOrMarkup, Option<Toggler>)> = Vec::new();
@@ -52,163 +47,128 @@ impl<'a, 'b> Printer<'a, 'b> {
             }
         }
 
-        let should_wrap = if let Some(element_len) =
-            element_attrs_len(&name, &id_name, &classes, &named_attrs, &body)
-        {
-            (self.line_len() + element_len) > self.options.line_length
-        } else {
-            true
+        let body_is_empty = match &body {
+            ElementBody::Void(_) => true,
+            ElementBody::Block(block) => block.markups.markups.is_empty(),
         };
-        let mut is_first_attr = true;
-
-        // element tag name
-        if let Some(html_name) = name {
-            self.print_inline_comment_and_whitespace(
-                html_name.span().start(),
-                indent_level,
-                preserve_blank_lines,
-            );
-            is_first_attr = false;
-            self.print_html_name(&html_name);
-            self.print_attr_comment(html_name.span().end());
-        }
-
-        // printing id
-        if let Some((pound_token, name)) = id_name {
-            match (is_first_attr, should_wrap) {
-                (false, false) => {
-                    self.write(" ");
-                }
-                (false, true) => {
-                    self.new_line(indent_level + 1);
-                }
-                (true, _) => {
-                    self.print_inline_comment_and_whitespace(
-                        pound_token.span().start(),
-                        indent_level,
-                        preserve_blank_lines,
-                    );
-                    is_first_attr = false;
-                }
-            }
-            self.write("#");
-            match name {
-                HtmlNameOrMarkup::HtmlName(html_name) => {
-                    self.print_html_name(&html_name);
-                    self.print_attr_comment(html_name.span().end());
-                }
-                HtmlNameOrMarkup::Markup(markup) => self.print_markup(markup, indent_level, true),
+        // attributes are either all on the element line, or one per line
+        self.group(directives.attrs, |p| {
+            let mut is_first_attr = true;
+
+            // element tag name
+            if let Some(html_name) = name {
+                is_first_attr = false;
+                p.print_html_name(&html_name);
+                p.print_attr_comment(html_name.span().end());
             }
-        }
 
-        // printing classes
-        for (dot_token, name, maybe_toggler) in classes {
-            match (is_first_attr, should_wrap) {
-                (false, true) => {
-                    self.new_line(indent_level + 1);
-                }
-                (false, false) => (),
-                (true, _) => {
-                    self.print_inline_comment_and_whitespace(
-                        dot_token.span().start(),
-                        indent_level,
-                        preserve_blank_lines,
-                    );
+            p.indent(|p| {
+                // printing id
+                if let Some((pound_token, name)) = id_name {
+                    if !is_first_attr {
+                        p.line();
+                        p.print_inline_comment_and_whitespace(pound_token.span().start(), false);
+                    }
                     is_first_attr = false;
+                    p.write("#");
+                    p.print_html_name_or_markup(name, indent_level);
                 }
-            }
-            self.write(".");
-            match name {
-                HtmlNameOrMarkup::HtmlName(html_name) => {
-                    self.print_html_name(&html_name);
-                    self.print_attr_comment(html_name.span().end());
-                }
-                HtmlNameOrMarkup::Markup(markup) => self.print_markup(markup, indent_level, true),
-            }
-            if let Some(toggler) = maybe_toggler {
-                self.write("[");
-                self.print_attr_comment(toggler.bracket_token.span.open().span().end());
-                self.print_toggle_expr(toggler.cond, indent_level);
-                self.write("]");
-                self.print_attr_comment(toggler.bracket_token.span.close().span().end());
-            }
-        }
 
-        // printing other attributes
-        for (name, attr_type) in named_attrs {
-            if should_wrap {
-                self.new_line(indent_level + 1);
-            } else {
-                self.write(" ");
-            }
-            self.print_html_attribute_name(&name);
-            match attr_type {
-                AttributeType::Normal { value, .. } => {
-                    self.write("=");
-                    let attr_indent = if should_wrap {
-                        indent_level + 1
-                    } else {
-                        indent_level
-                    };
-                    self.print_markup(value, attr_indent, true)
-                }
-                AttributeType::Optional { toggler, .. } => {
-                    self.write("=[");
-                    self.print_attr_comment(toggler.bracket_token.span.open().span().end());
-                    self.print_toggle_expr(toggler.cond, indent_level);
-                    self.write("]");
-                    self.print_attr_comment(toggler.bracket_token.span.close().span().end());
-                }
-                AttributeType::Empty(maybe_toggler) => {
+                // printing classes
+                for (dot_token, name, maybe_toggler) in classes {
+                    if !is_first_attr {
+                        let start = dot_token.span().start();
+                        // keep the comment apart from the previous attribute
+                        if p.has_leading_comments(start) {
+                            p.line();
+                        } else {
+                            p.soft_line();
+                        }
+                        p.print_inline_comment_and_whitespace(start, false);
+                    }
+                    is_first_attr = false;
+                    p.write(".");
+                    p.print_html_name_or_markup(name, indent_level);
                     if let Some(toggler) = maybe_toggler {
-                        self.write("[");
-                        self.print_attr_comment(toggler.bracket_token.span.open().span().end());
-                        self.print_toggle_expr(toggler.cond, indent_level);
-                        self.write("]");
-                        self.print_attr_comment(toggler.bracket_token.span.close().span().end());
+                        p.print_toggler(toggler, indent_level);
                     }
                 }
+
+                // printing other attributes
+                for (name, attr_type) in named_attrs {
+                    p.line();
+                    p.print_inline_comment_and_whitespace(name.span().start(), false);
+                    p.print_html_attribute_name(&name);
+                    match attr_type {
+                        AttributeType::Normal { value, .. } => {
+                            p.write("=");
+                            p.print_markup(value, indent_level + 1, true)
+                        }
+                        AttributeType::Optional { toggler, .. } => {
+                            p.write("=");
+                            p.print_toggler(toggler, indent_level);
+                        }
+                        AttributeType::Empty(maybe_toggler) => {
+                            if let Some(toggler) = maybe_toggler {
+                                p.print_toggler(toggler, indent_level);
+                            }
+                        }
+                    }
+                }
+            });
+
+            // the body goes on its own line below wrapped attributes
+            if let ElementBody::Block(_) = body {
+                if body_is_empty {
+                    p.write(" ");
+                } else {
+                    p.if_break(|p| p.hard_line(), |p| p.write(" "));
+                }
             }
-        }
+        });
 
         match body {
             ElementBody::Void(semi) => {
                 self.write(";");
                 self.print_attr_comment(semi.span().end());
             }
-            ElementBody::Block(block) => {
-                if should_wrap && !block.markups.markups.is_empty() {
-                    self.new_line(indent_level);
-                } else {
-                    self.write(" ");
-                }
-                self.print_block(block, indent_level);
-            }
+            ElementBody::Block(block) => self.print_block(block, indent_level, directives.body),
         }
     }
 
-    fn print_html_name(&mut self, name: &HtmlName) {
-        for child in name.name.pairs() {
-            match child.value() {
-                HtmlNameFragment::LitStr(lit) => self.write(&quote!(#lit).to_string()),
-                value => self.write(&value.to_string()),
-            }
-            if let Some(punct) = child.punct() {
-                match punct {
-                    HtmlNamePunct::Hyphen(_) => self.write("-"),
-                    HtmlNamePunct::Colon(_) => self.write(","),
-                }
+    fn print_html_name_or_markup(&mut self, name: HtmlNameOrMarkup, indent_level: usize) {
+        match name {
+            HtmlNameOrMarkup::HtmlName(html_name) => {
+                self.print_html_name(&html_name);
+                self.print_attr_comment(html_name.span().end());
             }
+            HtmlNameOrMarkup::Markup(markup) => self.print_markup(markup, indent_level, true),
         }
     }
 
+    fn print_toggler(&mut self, toggler: Toggler, indent_level: usize) {
+        let (open, close) = (
+            toggler.bracket_token.span.open().span(),
+            toggler.bracket_token.span.close().span(),
+        );
+        if self.contains_code_comments(open.end(), close.start()) {
+            self.print_verbatim(open.start(), close.end(), toggler.cond.to_token_stream());
+            self.print_attr_comment(close.end());
+            return;
+        }
+        self.write("[");
+        self.print_attr_comment(toggler.bracket_token.span.open().span().end());
+        self.print_toggle_expr(toggler.cond, indent_level);
+        self.write("]");
+        self.print_attr_comment(toggler.bracket_token.span.close().span().end());
+    }
+
+    fn print_html_name(&mut self, name: &HtmlName) {
+        self.write(&unparse_html_name(name));
+    }
+
     fn print_html_attribute_name(&mut self, name: &HtmlName) {
-        let value = name.to_string();
-        if value.contains('@') || value.contains('.') || value.starts_with(":") {
-            self.write(&quote!(#value).to_string());
-        } else {
-            self.write(&value);
-        }
+        self.write(&unparse_html_attribute_name(name));
     }
 }
 
@@ -216,6 +176,20 @@ impl<'a, 'b> Printer<'a, 'b> {
 mod test {
     use crate::testing::*;
 
+    test_default!(
+        namespaced_names,
+        r#"
+        html! { svg:svg xmlns:xlink="http://www.w3.org/1999/xlink" { svg:rect.a-b x="1" {} } }
+        "#,
+        r#"
+        html! {
+            svg:svg xmlns:xlink="http://www.w3.org/1999/xlink" {
+                svg:rect.a-b x="1" {}
+            }
+        }
+        "#
+    );
+
     test_default!(
         elements_with_contents,
         r#"
@@ -586,6 +560,44 @@ mod test {
         "##
     );
 
+    test_small_line!(
+        line_length_wide_characters,
+        r##"
+        html! {
+            p { "こんにちは世界こんにちは" }
+            p { "こんにちは" }
+        }
+        "##,
+        r##"
+        html! {
+            p {
+                "こんにちは世界こんにちは"
+            }
+            p { "こんにちは" }
+        }
+        "##
+    );
+
+    test_small_line!(
+        line_length_accents_and_combining_marks,
+        r##"
+        html! {
+            a title="éèêëàâäôö" {
+                "x"
+            }
+            p {
+                "ééééééééééé"
+            }
+        }
+        "##,
+        r##"
+        html! {
+            a title="éèêëàâäôö" { "x" }
+            p { "ééééééééééé" }
+        }
+        "##
+    );
+
     test_small_line!(
         line_length_element_body_no_expand_multi_el,
         r##"
@@ -682,10 +694,8 @@ mod test {
         html! {
             input
                 checked[
-                    example_rust_condition()
-                        .unwrap()
-                        .map(|x| x.to_string())
-                        .unwrap_or_default() == some_long_testing_variable_name
+                    example_rust_condition().unwrap().map(|x| x.to_string()).unwrap_or_default()
+                        == some_long_testing_variable_name
                 ];
         }
         "#
@@ -717,4 +727,18 @@ mod test {
         }
         "#
     );
+
+    test_default!(
+        toggler_with_comment_kept_verbatim,
+        r#"
+        html! {
+            input.a[on /* toggled */] checked[is_checked];
+        }
+        "#,
+        r#"
+        html! {
+            input.a[on /* toggled */] checked[is_checked];
+        }
+        "#
+    );
 }
@@ -1,61 +1,65 @@
-use syn::{Expr, spanned::Spanned as _};
+use anyhow::Result;
+use proc_macro2::{LineColumn, TokenStream};
+use quote::{ToTokens as _, quote};
+use syn::{Expr, Pat, Stmt, spanned::Spanned as _};
 
 use crate::{
+    embedded::{Original, Shape, Verbatim},
     format::line_column_to_byte,
     print::Printer,
-    unparse::{unparse_expr, unparse_stmts},
+    trivia::line_indent,
 };
 
-impl<'a, 'b> Printer<'a, 'b> {
+impl<'a> Printer<'a> {
     pub fn print_expr(&mut self, expr: Expr, indent_level: usize) {
         let span = expr.span();
-        let lines: Vec<String> = match std::panic::catch_unwind(|| match expr {
-            Expr::Block(expr_block) => {
-                unparse_stmts(&expr_block.block.stmts, self.base_indent + indent_level)
-            }
-            _ => unparse_expr(&expr, self.base_indent + indent_level),
-        }) {
-            Ok(lines) => lines,
-            Err(_) => {
-                let start_byte = line_column_to_byte(self.source, span.start());
-                let end_byte = line_column_to_byte(self.source, span.end());
-                let original_text = self.source.byte_slice(start_byte..end_byte).to_string();
-                eprintln!(
-                    "Warning: prettyplease panicked formatting expression, leaving unchanged: {original_text}"
-                );
-                vec![original_text]
-            }
+        if self.contains_code_comments(span.start(), span.end()) {
+            self.print_verbatim(span.start(), span.end(), expr.to_token_stream());
+            return;
+        }
+        let (lines, is_block) = match &expr {
+            Expr::Block(expr_block) => (
+                self.format_stmts(&expr_block.block.stmts, indent_level),
+                !matches!(expr_block.block.stmts.as_slice(), [Stmt::Expr(_, None)]),
+            ),
+            _ => (self.format_expr(&expr, indent_level), false),
         };
 
         match lines.len() {
             0 => (),
+            // statements need their block
+            1 if is_block => self.write(&format!("{{ {} }}", lines[0].trim())),
             1 => self.write(lines[0].trim()),
             _ => {
                 self.write("{\n");
                 self.write(&lines.join("\n"));
-                self.new_line(indent_level);
+                self.hard_line();
                 self.write("}");
             }
         }
     }
 
     pub fn print_toggle_expr(&mut self, expr: Expr, indent_level: usize) {
+        let span = expr.span();
+        if self.contains_code_comments(span.start(), span.end()) {
+            self.print_verbatim(span.start(), span.end(), expr.to_token_stream());
+            return;
+        }
         match expr {
             Expr::Block(expr_block) => {
-                let lines =
-                    unparse_stmts(&expr_block.block.stmts, self.base_indent + indent_level + 1);
+                let lines = self.format_stmts(&expr_block.block.stmts, indent_level + 1);
 
                 if lines.is_empty() || (lines.len() == 1 && lines[0].trim().is_empty()) {
                     self.write("{}");
                 } else {
                     self.write("{\n");
                     self.write(&lines.join("\n"));
-                    self.new_line(indent_level + 1);
+                    self.hard_line();
                     self.write("}");
                 }
             }
             _ => {
-                let lines = unparse_expr(&expr, self.base_indent + indent_level + 1);
+                let lines = self.format_expr(&expr, indent_level + 1);
 
                 match lines.len() {
                     0 => (),
@@ -63,12 +67,144 @@ impl<'a, 'b> Printer<'a, 'b> {
                     _ => {
                         self.write("\n");
                         self.write(&lines.join("\n"));
-                        self.new_line(indent_level + 1);
+                        self.hard_line();
                     }
                 }
             }
         }
     }
+
+    /// Lines of `expr`, indented like the body of a block at `indent_level`
+    pub fn format_expr(&self, expr: &Expr, indent_level: usize) -> Vec<String> {
+        let span = expr.span();
+        let original = self.original(span.start(), span.end(), expr.to_token_stream());
+        let shape = self.shape(indent_level);
+        let formatted = self.fit_at_column(shape, |shape| {
+            self.formatter.format_expr(expr, &original, shape)
+        });
+        self.or_verbatim(formatted, &original, span.start(), shape)
+    }
+
+    /// Lines of `pat`, the first one without indentation as it follows `@for`, `let`... on a line
+    /// at `indent_level`
+    pub fn format_pat(&self, pat: &Pat, indent_level: usize) -> Vec<String> {
+        let span = pat.span();
+        let original = self.original(span.start(), span.end(), pat.to_token_stream());
+        let shape = self.shape(indent_level.saturating_sub(1));
+        let formatted = self.fit_at_column(shape, |shape| {
+            self.formatter.format_pat(pat, &original, shape)
+        });
+        let mut lines = self.or_verbatim(formatted, &original, span.start(), shape);
+        if let Some(first) = lines.first_mut() {
+            *first = first.trim_start().to_string();
+        }
+        lines
+    }
+
+    /// Lines of `stmts`, indented like the body of a block at `indent_level`
+    pub fn format_stmts(&self, stmts: &[Stmt], indent_level: usize) -> Vec<String> {
+        let (original, start) = match (stmts.first(), stmts.last()) {
+            (Some(first), Some(last)) => (
+                self.original(first.span().start(), last.span().end(), quote!(#(#stmts)*)),
+                first.span().start(),
+            ),
+            _ => (Original::default(), LineColumn { line: 1, column: 0 }),
+        };
+        let shape = self.shape(indent_level);
+        let formatted = self.fit_at_column(shape, |shape| {
+            self.formatter.format_stmts(stmts, &original, shape)
+        });
+        self.or_verbatim(formatted, &original, start, shape)
+    }
+
+    fn shape(&self, indent_level: usize) -> Shape {
+        Shape {
+            indent: self.base_indent + indent_level,
+            width: self.options.line_length,
+        }
+    }
+
+    /// Format with `format` for a single line starting at the current column, multiple lines
+    /// start on their own lines and get the whole width
+    fn fit_at_column(
+        &self,
+        shape: Shape,
+        format: impl Fn(Shape) -> Result<Vec<String>>,
+    ) -> Result<Vec<String>> {
+        let block_column = (shape.indent + 1) * self.indent_str.len();
+        let single_line = Shape {
+            width: (shape.width + block_column).saturating_sub(self.column),
+            ..shape
+        };
+        let lines = format(single_line)?;
+        match lines.len() > 1 && single_line.width != shape.width {
+            true => format(shape),
+            false => Ok(lines),
+        }
+    }
+
+    /// The code as written when the formatter fails, the failure is reported at `start`
+    fn or_verbatim(
+        &self,
+        formatted: Result<Vec<String>>,
+        original: &Original,
+        start: LineColumn,
+        shape: Shape,
+    ) -> Vec<String> {
+        formatted.unwrap_or_else(|err| {
+            self.failures.borrow_mut().insert(
+                start,
+                format!("failed to format rust code ({err:#}), it was left unchanged"),
+            );
+            Verbatim::reindent(original, shape)
+        })
+    }
+
+    /// prettyplease drops comments, rust code containing some is kept as written
+    pub fn contains_code_comments(&self, start: LineColumn, end: LineColumn) -> bool {
+        self.trivia.comments_between(start, end).next().is_some()
+    }
+
+    /// The source between `start` and `end`, made of `tokens`, to be put on its own lines
+    fn original(&self, start: LineColumn, end: LineColumn, tokens: TokenStream) -> Original {
+        // code after other code on its line has no indentation of its own
+        let indent = line_indent(self.source, start.line);
+        let indent = (start.column <= indent).then_some(indent);
+        Original::new(&self.text(start, end), tokens, start.line, indent)
+    }
+
+    fn text(&self, start: LineColumn, end: LineColumn) -> String {
+        let start_byte = line_column_to_byte(self.source, start);
+        let end_byte = line_column_to_byte(self.source, end);
+        self.source.byte_slice(start_byte..end_byte).to_string()
+    }
+
+    /// Print the source between `start` and `end`, made of `tokens`, re-indented relative to the
+    /// current line. The lines inside multi-line literals are left untouched.
+    pub fn print_verbatim(&mut self, start: LineColumn, end: LineColumn, tokens: TokenStream) {
+        let original = Original::new(
+            &self.text(start, end),
+            tokens,
+            start.line,
+            Some(line_indent(self.source, start.line)),
+        );
+
+        for (idx, line) in original.lines.iter().enumerate() {
+            // trailing whitespace at the end of a line inside a literal is part of it
+            let line = match original.literal_lines.contains(&(idx + 1)) {
+                true => line,
+                false => line.trim_end(),
+            };
+            if idx == 0 {
+                self.write(line);
+            } else if original.literal_lines.contains(&idx) {
+                self.write(&format!("\n{line}"));
+            } else {
+                self.hard_line();
+                self.write(line);
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -3,7 +3,7 @@ use crate::{
     vendor::ast::{Element, Markup},
 };
 
-impl<'a, 'b> Printer<'a, 'b> {
+impl<'a> Printer<'a> {
     pub fn print_markup<E: Into<Element>>(
         &mut self,
         markup: Markup<E>,
@@ -11,14 +11,14 @@ impl<'a, 'b> Printer<'a, 'b> {
         preserve_blank_lines: bool,
     ) {
         match markup {
-            Markup::Lit(html_lit) => self.print_lit(html_lit, indent_level, preserve_blank_lines),
+            Markup::Lit(html_lit) => self.print_lit(html_lit, preserve_blank_lines),
             Markup::Splice { paren_token, expr } => {
                 self.print_splice(expr, paren_token, indent_level, preserve_blank_lines)
             }
             Markup::Element(element) => {
                 self.print_element_with_contents(element.into(), indent_level, preserve_blank_lines)
             }
-            Markup::Block(block) => self.print_block(block, indent_level),
+            Markup::Block(block) => self.print_block(block, indent_level, None),
             Markup::ControlFlow(control_flow) => {
                 self.print_control_flow(control_flow, indent_level)
             }
@@ -44,4 +44,53 @@ mod test {
         }
         "#
     );
+
+    test_default!(
+        blank_lines_between_markups,
+        r#"
+        html! {
+            div {
+
+                p { "a" }
+
+                p { "b" }
+                (c)
+
+
+                "d"
+            }
+        }
+        "#,
+        r#"
+        html! {
+            div {
+                p { "a" }
+
+                p { "b" }
+                (c)
+
+                "d"
+            }
+        }
+        "#
+    );
+
+    test_small_line!(
+        nested_blocks_collapse_together,
+        r#"
+        html! {
+            p { { "a" } { "b" } }
+            p { { "a long" } { "text" } }
+        }
+        "#,
+        r#"
+        html! {
+            p { { "a" } { "b" } }
+            p {
+                { "a long" }
+                { "text" }
+            }
+        }
+        "#
+    );
 }
@@ -1,93 +1,275 @@
+use std::{cell::RefCell, collections::BTreeMap, path::Path};
+
 use crop::Rope;
+use proc_macro2::LineColumn;
+use syn::spanned::Spanned as _;
 
-use crate::{ast::*, collect::MaudMacro, format::FormatOptions};
+use crate::{
+    ast::*,
+    collect::MaudMacro,
+    embedded::RustFormatter,
+    format::{FormatOptions, report},
+    line_length::display_width,
+    print::{directive::Layout, doc::Doc},
+    trivia::{TriviaMap, source_end},
+};
 
 mod block;
 mod comment_and_whitespace;
 mod control_flow;
+pub mod directive;
+mod doc;
 mod element;
 mod expr;
 mod lit;
 mod markup;
 mod splice;
 
-pub fn print<'b>(
+pub fn print(
     ast: Markups<Element>,
-    mac: &'b MaudMacro<'b>,
+    mac: &MaudMacro<'_>,
     source: &Rope,
+    trivia: &TriviaMap,
+    path: Option<&Path>,
     options: &FormatOptions,
+    formatter: &dyn RustFormatter,
 ) -> String {
     #[cfg(debug_assertions)]
     dbg!(&ast); // print ast when debugging (not release mode)
 
-    let mut printer = Printer {
-        lines: Vec::new(),
-        buf: String::new(),
-        base_indent: mac.indent.tabs + mac.indent.spaces / 4,
-        indent_str: &String::from(" ").repeat(4),
-        mac,
-        source,
-        options,
-    };
+    // rust code before the macro on the same line (indent, `let x = `, ...)
+    let start = mac.macro_.path.span().start();
+    let prefix: String = source
+        .line(start.line - 1)
+        .chars()
+        .take(start.column)
+        .collect();
 
-    printer.print_ast(ast);
+    let base_indent = mac.indent.tabs + mac.indent.spaces / 4;
+    // printed again as long as the formatter has new results for the rust code
+    loop {
+        let mut printer = Printer::new(
+            source,
+            trivia,
+            base_indent,
+            display_width(&prefix),
+            options,
+            formatter,
+        );
+        printer.print_ast(ast.clone(), mac);
+        let failures = printer.failures.take();
+        let printed = printer.finish();
+        if !formatter.flush() {
+            report_failures(path, failures);
+            return printed;
+        }
+    }
+}
 
-    printer.finish()
+/// Print bare markups, without any surrounding macro
+pub fn print_fragment(
+    ast: Markups<Element>,
+    source: &Rope,
+    trivia: &TriviaMap,
+    base_indent: usize,
+    options: &FormatOptions,
+    formatter: &dyn RustFormatter,
+) -> String {
+    loop {
+        let mut printer = Printer::new(source, trivia, base_indent, 0, options, formatter);
+        printer.print_fragment(ast.clone());
+        let failures = printer.failures.take();
+        let printed = printer.finish();
+        if !formatter.flush() {
+            report_failures(None, failures);
+            return printed;
+        }
+    }
+}
+
+/// Report the rust code the formatter failed on, once the macro is printed for the last time
+fn report_failures(path: Option<&Path>, failures: BTreeMap<LineColumn, String>) {
+    for (loc, message) in failures {
+        report(path, loc, message);
+    }
 }
 
-struct Printer<'a, 'b> {
-    lines: Vec<String>,
-    buf: String,
+/// Builds the [`Doc`] of a macro, its layout is chosen when rendering it
+struct Printer<'a> {
+    doc: Vec<Doc>,
+    /// Where the first line starts
+    start_column: usize,
+    /// Column at the end of `doc` when every group is expanded, where rust code would start
+    column: usize,
+    /// Indentation levels of `doc`, from `base_indent`
+    indent_depth: usize,
     base_indent: usize,
     indent_str: &'a str,
-    mac: &'b MaudMacro<'b>,
     source: &'a Rope,
+    trivia: &'a TriviaMap,
     options: &'a FormatOptions,
+    formatter: &'a dyn RustFormatter,
+    /// Rust code the formatter failed on, by start, kept as written
+    failures: RefCell<BTreeMap<LineColumn, String>>,
 }
 
-impl<'a, 'b> Printer<'a, 'b> {
-    fn print_ast(&mut self, ast: Markups<Element>) {
-        let indent_level = 0;
+impl<'a> Printer<'a> {
+    fn new(
+        source: &'a Rope,
+        trivia: &'a TriviaMap,
+        base_indent: usize,
+        start_column: usize,
+        options: &'a FormatOptions,
+        formatter: &'a dyn RustFormatter,
+    ) -> Self {
+        Printer {
+            doc: Vec::new(),
+            start_column,
+            column: start_column,
+            indent_depth: 0,
+            base_indent,
+            indent_str: "    ",
+            source,
+            trivia,
+            options,
+            formatter,
+            failures: RefCell::default(),
+        }
+    }
 
-        self.write(&self.mac.macro_name);
+    fn print_ast(&mut self, ast: Markups<Element>, mac: &MaudMacro) {
+        self.write(&mac.macro_name);
         self.write("! ");
 
+        let delimiters = mac.macro_.delimiter.span();
         if ast.markups.is_empty() {
-            self.write("{}")
+            self.write("{}");
+            return;
+        }
+
+        let layout = if !self.options.single_line_macros
+            || self.contains_comments(delimiters.open().start(), delimiters.close().end())
+            || markups_must_expand(&ast)
+        {
+            Some(Layout::Expand)
         } else {
-            self.write("{");
-            self.print_attr_comment(self.mac.macro_.delimiter.span().open().end());
-            for markup in ast.markups {
-                self.new_line(indent_level + 1);
-                self.print_markup(markup, indent_level + 1, true);
+            None
+        };
+        self.group(layout, |p| {
+            p.write("{");
+            p.print_attr_comment(delimiters.open().end());
+            p.indent(|p| {
+                for markup in ast.markups {
+                    p.line();
+                    p.print_markup(markup, 1, true);
+                }
+                p.print_comments_before(delimiters.close().start());
+            });
+            p.line();
+            p.write("}");
+        });
+        // a comment after the macro is part of the surrounding rust code
+    }
+
+    fn print_fragment(&mut self, ast: Markups<Element>) {
+        let indent_level = 0;
+
+        self.write(&self.indent_str.repeat(self.base_indent));
+        for (idx, markup) in ast.markups.into_iter().enumerate() {
+            if idx > 0 {
+                self.hard_line();
             }
-            self.print_trailing_comments(*self.mac.macro_.delimiter.span(), indent_level + 1);
-            self.new_line(indent_level);
+            // no blank line before the first markup
+            self.print_markup(markup, indent_level, idx > 0);
+        }
+        self.print_comments_before(source_end(self.source));
+    }
 
-            let close_location = self.mac.macro_.delimiter.span().close().end();
-            self.print_inline_comment_and_whitespace(close_location, indent_level, true);
-            self.write("}");
-            self.print_attr_comment(close_location);
+    fn write(&mut self, content: &str) {
+        if !content.is_empty() {
+            self.column = match content.rsplit_once('\n') {
+                Some((_, last_line)) => display_width(last_line),
+                None => self.column + display_width(content),
+            };
+            self.doc.push(Doc::Text(content.to_string()));
         }
     }
 
-    fn new_line(&mut self, indent_level: usize) {
-        self.lines.push(self.buf.clone());
-        self.buf = String::from(self.indent_str).repeat(self.base_indent + indent_level);
+    /// A space, or a line break if the enclosing group is expanded
+    fn line(&mut self) {
+        self.new_line();
+        self.doc.push(Doc::Line);
     }
 
-    fn write(&mut self, content: &str) {
-        self.buf += content;
+    /// Nothing, or a line break if the enclosing group is expanded
+    fn soft_line(&mut self) {
+        self.new_line();
+        self.doc.push(Doc::SoftLine);
+    }
+
+    fn hard_line(&mut self) {
+        self.new_line();
+        self.doc.push(Doc::HardLine);
+    }
+
+    /// An empty line, only kept if the enclosing group is expanded
+    fn blank_line(&mut self) {
+        self.new_line();
+        self.doc.push(Doc::IfBreak {
+            broken: vec![Doc::HardLine],
+            flat: Vec::new(),
+        });
+    }
+
+    fn new_line(&mut self) {
+        self.column = (self.base_indent + self.indent_depth) * self.indent_str.len();
     }
 
-    fn line_len(&self) -> usize {
-        self.buf.len()
+    fn indent(&mut self, build: impl FnOnce(&mut Self)) {
+        self.indent_depth += 1;
+        let contents = self.build(build);
+        self.indent_depth -= 1;
+        self.doc.push(Doc::Indent(contents));
     }
 
-    fn finish(mut self) -> String {
-        self.new_line(0);
-        self.lines.join("\n")
+    /// Print on a single line if it fits, unless `layout` forces it
+    fn group(&mut self, layout: Option<Layout>, build: impl FnOnce(&mut Self)) {
+        let contents = self.build(build);
+        self.doc.push(Doc::Group { layout, contents });
     }
+
+    fn if_break(&mut self, broken: impl FnOnce(&mut Self), flat: impl FnOnce(&mut Self)) {
+        let column = self.column;
+        let broken = self.build(broken);
+        let broken_column = std::mem::replace(&mut self.column, column);
+        let flat = self.build(flat);
+        self.column = broken_column;
+        self.doc.push(Doc::IfBreak { broken, flat });
+    }
+
+    fn build(&mut self, build: impl FnOnce(&mut Self)) -> Vec<Doc> {
+        let outer = std::mem::take(&mut self.doc);
+        build(self);
+        std::mem::replace(&mut self.doc, outer)
+    }
+
+    fn finish(self) -> String {
+        doc::render(
+            &self.doc,
+            self.base_indent,
+            self.start_column,
+            self.options.line_length,
+        )
+    }
+}
+
+/// Elements and control flows are never printed on the same line as their siblings
+fn markups_must_expand<E: Into<Element>>(markups: &Markups<E>) -> bool {
+    markups.markups.iter().any(|markup| match markup {
+        Markup::Element(_) | Markup::ControlFlow(_) => true,
+        Markup::Block(block) => markups_must_expand(&block.markups),
+        _ => false,
+    })
 }
 
 #[cfg(test)]
@@ -101,4 +283,100 @@ mod test {
         "maud::html!{ }",
         "maud::html! {}"
     );
+
+    test_default!(
+        single_line_macro_expanded_by_default,
+        r#"
+        html! { "x" }
+        "#,
+        r#"
+        html! {
+            "x"
+        }
+        "#
+    );
+
+    test_single_line_macros!(
+        single_line_macro,
+        r#"
+        fn render() -> Markup {
+            let x = html! {
+                "Hello " (name)
+            };
+        }
+        "#,
+        r#"
+        fn render() -> Markup {
+            let x = html! { "Hello " (name) };
+        }
+        "#
+    );
+
+    test_single_line_macros!(
+        single_line_macro_with_comment,
+        r#"
+        html! { "x" }  // trailing comment
+        html! {
+            // inner comment
+            "x"
+        }
+        "#,
+        r#"
+        html! { "x" }  // trailing comment
+        html! {
+            // inner comment
+            "x"
+        }
+        "#
+    );
+
+    test_default!(
+        trailing_comment_after_macro,
+        r#"
+        fn render() -> Markup {
+            html! {
+                "x"
+            }  // trailing comment
+        }
+        "#,
+        r#"
+        fn render() -> Markup {
+            html! {
+                "x"
+            }  // trailing comment
+        }
+        "#
+    );
+
+    test_single_line_macros!(
+        single_line_macro_with_elements,
+        r#"
+        html! { p { "x" } }
+        "#,
+        r#"
+        html! {
+            p { "x" }
+        }
+        "#
+    );
+
+    test_single_line_macros!(
+        single_line_macro_counts_prefix,
+        r#"
+        fn render() -> Markup {
+            let greeting_with_a_long_name = html! { "Hello " (name) ", how are you doing today, my friend?" };
+            let greeting_with_a_long_name = html! { "Hello " (name) ", how are you doing?" };
+        }
+        "#,
+        r#"
+        fn render() -> Markup {
+            let greeting_with_a_long_name = html! {
+                "Hello "
+                (name)
+                ", how are you doing today, my friend?"
+            };
+            let greeting_with_a_long_name = html! { "Hello " (name) ", how are you doing?" };
+        }
+        "#
+    );
 }
@@ -1,8 +1,9 @@
+use quote::ToTokens as _;
 use syn::{Expr, spanned::Spanned as _, token::Paren};
 
 use crate::print::Printer;
 
-impl<'a, 'b> Printer<'a, 'b> {
+impl<'a> Printer<'a> {
     pub fn print_splice(
         &mut self,
         expr: Expr,
@@ -10,20 +11,29 @@ impl<'a, 'b> Printer<'a, 'b> {
         indent_level: usize,
         preserve_blank_lines: bool,
     ) {
-        self.print_inline_comment_and_whitespace(
-            paren.span.span().start(),
-            indent_level,
-            preserve_blank_lines,
-        );
+        self.print_inline_comment_and_whitespace(paren.span.span().start(), preserve_blank_lines);
+
+        let (open, close) = (paren.span.open().span(), paren.span.close().span());
+        let comment_after_open = self.attr_comment(open.end());
+        let has_inner_comments = self
+            .trivia
+            .comments_between(open.end(), close.start())
+            .any(|comment| Some(comment) != comment_after_open);
+        if has_inner_comments {
+            self.print_verbatim(open.start(), close.end(), expr.to_token_stream());
+            self.print_attr_comment(close.end());
+            return;
+        }
+
         self.write("(");
 
         if self.print_attr_comment(paren.span.open().span().end()) {
-            // expand if comment or line_length exceeded
-            // NOTE: comments on splice lines aren't supported
-            //       since syn/prettyprinter do not support them
-            self.new_line(indent_level + 1);
-            self.print_expr(expr, indent_level + 1);
-            self.new_line(indent_level);
+            // expand if comment, the code comes on the next line
+            self.indent(|p| {
+                p.hard_line();
+                p.print_expr(expr, indent_level + 1);
+            });
+            self.hard_line();
             self.write(")");
         } else {
             self.print_expr(expr, indent_level);
@@ -35,7 +45,14 @@ impl<'a, 'b> Printer<'a, 'b> {
 
 #[cfg(test)]
 mod test {
-    use crate::testing::*;
+    use std::sync::LazyLock;
+
+    use crate::{format::FormatOptions, testing::*};
+
+    static WIDE_OPTIONS: LazyLock<FormatOptions> = LazyLock::new(|| FormatOptions {
+        line_length: 140,
+        ..Default::default()
+    });
 
     test_default!(
         escaping,
@@ -117,6 +134,87 @@ mod test {
         "##
     );
 
+    test_with_options!(
+        line_length_wide_splice,
+        WIDE_OPTIONS,
+        r##"
+        html! {
+            (super_long_splice.with_a_super_long_method().and_an_other_super_super_long_method_to_call_afer().unwarp())
+        }
+        "##,
+        r##"
+        html! {
+            (super_long_splice.with_a_super_long_method().and_an_other_super_super_long_method_to_call_afer().unwarp())
+        }
+        "##
+    );
+
+    test_small_line!(
+        line_length_narrow_splice,
+        r##"
+        html! {
+            p { "Total: " (price_of(total, rate)) }
+            (format_price(total_with_taxes, currency))
+            @let total = items.iter().map(|i| i.price).sum();
+        }
+        "##,
+        r##"
+        html! {
+            p {
+                "Total: "
+                (price_of(total, rate))
+            }
+            ({
+                format_price(
+                    total_with_taxes,
+                    currency,
+                )
+            })
+            @let total = items
+                .iter()
+                .map(|i| i.price)
+                .sum();
+        }
+        "##
+    );
+
+    test_small_line!(
+        line_length_uses_formatted_splice,
+        r##"
+        html! {
+            p { ( a . b ( x , y ) ) "abcdef" }
+        }
+        "##,
+        r##"
+        html! {
+            p { (a.b(x, y)) "abcdef" }
+        }
+        "##
+    );
+
+    test_default!(
+        line_length_multi_line_splice_collapsed,
+        r##"
+        html! {
+            p {
+                (
+                    user
+                        .name()
+                )
+            }
+            a href=(
+                url
+            ) { "link" }
+        }
+        "##,
+        r##"
+        html! {
+            p { (user.name()) }
+            a href=(url) { "link" }
+        }
+        "##
+    );
+
     test_default!(
         blank_line_above_splice,
         r#"
@@ -138,4 +236,36 @@ mod test {
         }
         "#
     );
+
+    test_default!(
+        splice_with_comments_kept_verbatim,
+        r#"
+        html! {
+            div {
+            (format!(
+                "{}",  // the name
+                name
+            ))
+            }
+            (concat!("a
+           b", /* c */ "d"))
+            (x // trailing
+            )
+        }
+        "#,
+        r#"
+        html! {
+            div {
+                (format!(
+                    "{}",  // the name
+                    name
+                ))
+            }
+            (concat!("a
+           b", /* c */ "d"))
+            (x // trailing
+            )
+        }
+        "#
+    );
 }
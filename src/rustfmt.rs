@@ -0,0 +1,227 @@
+use std::{
+    collections::HashMap,
+    env,
+    ffi::OsString,
+    fmt, io,
+    io::Write as _,
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Stdio},
+    string::FromUtf8Error,
+    sync::{LazyLock, Mutex, PoisonError},
+    thread,
+};
+
+use cargo_metadata::MetadataCommand;
+
+/// Name of the environment variable overriding the rustfmt executable, same as `cargo fmt`
+const RUSTFMT_ENV: &str = "RUSTFMT";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum RustfmtStage {
+    /// Run rustfmt before maudfmt
+    Before,
+    /// Run rustfmt after maudfmt
+    After,
+}
+
+#[derive(Debug)]
+pub enum RustfmtError {
+    /// rustfmt could not be started, usually because it is not installed
+    Spawn {
+        program: OsString,
+        source: io::Error,
+    },
+    /// Writing the source to rustfmt or reading its output failed
+    Io(io::Error),
+    /// rustfmt exited with an error, usually because the source is invalid
+    Failed { status: ExitStatus, stderr: String },
+    /// rustfmt output is not valid utf8
+    InvalidOutput(FromUtf8Error),
+}
+
+impl fmt::Display for RustfmtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RustfmtError::Spawn { program, .. } => {
+                write!(f, "rustfmt: failed to run `{}`", program.to_string_lossy())
+            }
+            RustfmtError::Io(_) => write!(f, "rustfmt: failed to communicate with rustfmt"),
+            RustfmtError::Failed { status, stderr } => {
+                write!(f, "rustfmt: failed ({status})")?;
+                if !stderr.trim().is_empty() {
+                    write!(f, "\n{}", stderr.trim_end())?;
+                }
+                Ok(())
+            }
+            RustfmtError::InvalidOutput(_) => write!(f, "rustfmt: output is not valid utf8"),
+        }
+    }
+}
+
+impl std::error::Error for RustfmtError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RustfmtError::Spawn { source, .. } => Some(source),
+            RustfmtError::Io(source) => Some(source),
+            RustfmtError::Failed { .. } => None,
+            RustfmtError::InvalidOutput(source) => Some(source),
+        }
+    }
+}
+
+pub struct Rustfmt {
+    pub stage: RustfmtStage,
+    /// Edition to use, detected from the closest `Cargo.toml` when missing
+    pub edition: Option<String>,
+    pub config_path: Option<PathBuf>,
+    /// Extra arguments, passed as is
+    pub args: Vec<OsString>,
+}
+
+impl Rustfmt {
+    /// Format `source` with rustfmt, `path` is the file the source comes from, if any
+    pub fn run(&self, source: &str, path: Option<&Path>) -> Result<String, RustfmtError> {
+        let has_edition_arg = self.args.iter().any(|arg| {
+            let arg = arg.to_string_lossy();
+            arg == "--edition" || arg.starts_with("--edition=")
+        });
+        // an explicit `--edition` argument wins over the detected one
+        let edition = if has_edition_arg {
+            None
+        } else {
+            self.edition.clone().or_else(|| detect_edition(path))
+        };
+        run(
+            source,
+            edition.as_deref(),
+            self.config_path.as_deref(),
+            &self.args,
+        )
+    }
+}
+
+/// Format `source` with rustfmt, passing it `edition`, `config_path` and `args`
+pub fn run(
+    source: &str,
+    edition: Option<&str>,
+    config_path: Option<&Path>,
+    args: &[OsString],
+) -> Result<String, RustfmtError> {
+    let program = program();
+
+    let mut command = Command::new(&program);
+    if let Some(edition) = edition {
+        command.arg("--edition").arg(edition);
+    }
+    if let Some(config_path) = config_path {
+        command.arg("--config-path").arg(config_path);
+    }
+    command.args(args);
+
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|source| RustfmtError::Spawn {
+            program: program.clone(),
+            source,
+        })?;
+
+    // write from another thread, rustfmt could fill its stdout before reading all its stdin
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = source.to_string();
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+
+    let output = child.wait_with_output().map_err(RustfmtError::Io)?;
+    let written = writer.join().expect("stdin writer should not panic");
+
+    // rustfmt stops reading its input when it fails, its error explains the broken pipe
+    if !output.status.success() {
+        return Err(RustfmtError::Failed {
+            status: output.status,
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        });
+    }
+    written.map_err(RustfmtError::Io)?;
+
+    String::from_utf8(output.stdout).map_err(RustfmtError::InvalidOutput)
+}
+
+/// Run rustfmt with `args` and the standard streams of this process
+pub fn passthrough(args: &[OsString]) -> Result<ExitStatus, RustfmtError> {
+    let program = program();
+    Command::new(&program)
+        .args(args)
+        .status()
+        .map_err(|source| RustfmtError::Spawn { program, source })
+}
+
+/// Returns false if rustfmt called with `args` doesn't print the formatted source
+pub fn emits_source(args: &[OsString]) -> bool {
+    let mut args = args.iter().map(|arg| arg.to_string_lossy());
+    while let Some(arg) = args.next() {
+        match arg.as_ref() {
+            "--check" | "-h" | "--help" | "-V" | "--version" | "--print-config" => return false,
+            "--emit" if args.next().is_some_and(|emit| emit != "stdout") => return false,
+            arg if arg.starts_with("--emit=") && arg != "--emit=stdout" => return false,
+            arg if arg.starts_with("--print-config=") => return false,
+            _ => {}
+        }
+    }
+    true
+}
+
+/// The rustfmt executable, `RUSTFMT` overrides it like for `cargo fmt`
+pub fn program() -> OsString {
+    env::var_os(RUSTFMT_ENV).unwrap_or_else(|| OsString::from("rustfmt"))
+}
+
+/// Edition of the package of `path`, or of the current directory for sources without a path
+pub fn detect_edition(path: Option<&Path>) -> Option<String> {
+    let dir = match path.and_then(Path::parent) {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => env::current_dir().ok()?,
+    };
+    find_edition(&dir)
+}
+
+/// Edition of the package containing `dir`, the one of the closest `Cargo.toml`
+pub fn find_edition(dir: &Path) -> Option<String> {
+    static EDITIONS: LazyLock<Mutex<HashMap<PathBuf, Option<String>>>> =
+        LazyLock::new(Mutex::default);
+
+    let manifest = dir
+        .ancestors()
+        .map(|dir| dir.join("Cargo.toml"))
+        .find(|manifest| manifest.is_file())?
+        .canonicalize()
+        .ok()?;
+    // every file of a package has the same edition, ask cargo once
+    EDITIONS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .entry(manifest)
+        .or_insert_with_key(|manifest| package_edition(manifest))
+        .clone()
+}
+
+/// Edition of the package of `manifest` read by `cargo metadata`, which resolves the editions
+/// inherited from the workspace. None for a workspace manifest without a package.
+fn package_edition(manifest: &Path) -> Option<String> {
+    let metadata = MetadataCommand::new()
+        .no_deps()
+        .manifest_path(manifest)
+        .exec()
+        .ok()?;
+    metadata
+        .packages
+        .into_iter()
+        .find(|package| {
+            package
+                .manifest_path
+                .canonicalize()
+                .is_ok_and(|path| path == manifest)
+        })
+        .map(|package| package.edition.to_string())
+}
@@ -8,38 +8,129 @@ pub static SMALL_LINE_OPTIONS: LazyLock<FormatOptions> = LazyLock::new(|| Format
     ..Default::default()
 });
 
-macro_rules! test_default {
-    ($title: ident, $content: literal, $expected: literal ) => {
+pub static SINGLE_LINE_MACROS_OPTIONS: LazyLock<FormatOptions> = LazyLock::new(|| FormatOptions {
+    single_line_macros: true,
+    ..Default::default()
+});
+
+pub static DOC_COMMENTS_OPTIONS: LazyLock<FormatOptions> = LazyLock::new(|| FormatOptions {
+    format_doc_comments: true,
+    ..Default::default()
+});
+
+macro_rules! test_with_options {
+    ($title: ident, $options: expr, $content: literal, $expected: literal $(, $check: block)? ) => {
         #[test]
         fn $title() {
-            // check formatter works as expected
+            // check formatter works as expected, without leaving a macro unformatted
+            let (formatted, diagnostics) =
+                crate::count_diagnostics(|| crate::try_fmt_file($content, &$options));
             pretty_assertions::assert_eq!(
-                crate::try_fmt_file($content, &DEFAULT_OPTIONS).expect("should be able to parse"),
+                formatted.expect("should be able to parse"),
                 String::from($expected)
             );
+            assert_eq!(diagnostics, 0, "formatting reported diagnostics");
             // check that `$expected` is a valid maud macro
-            crate::try_fmt_file($expected, &DEFAULT_OPTIONS)
-                .expect("expected should be parsable and valid maud");
+            let (formatted, diagnostics) =
+                crate::count_diagnostics(|| crate::try_fmt_file($expected, &$options));
+            formatted.expect("expected should be parsable");
+            assert_eq!(diagnostics, 0, "expected should be valid maud");
+            $($check)?
         }
     };
 }
 
+/// [`test_with_options`], also checking that formatting the output again doesn't change it
+macro_rules! test_stable_with_options {
+    ($title: ident, $options: expr, $content: literal, $expected: literal ) => {
+        test_with_options!($title, $options, $content, $expected, {
+            // check that a second pass doesn't change the output
+            let unstable =
+                crate::try_verify_file($content, None, &$options).expect("should be able to parse");
+            assert!(
+                unstable.is_empty(),
+                "formatting is not idempotent:\n{}",
+                unstable
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            );
+        });
+    };
+}
+
+macro_rules! test_default {
+    ($title: ident, $content: literal, $expected: literal ) => {
+        test_stable_with_options!($title, DEFAULT_OPTIONS, $content, $expected);
+    };
+}
+
 macro_rules! test_small_line {
+    ($title: ident, $content: literal, $expected: literal ) => {
+        test_stable_with_options!($title, SMALL_LINE_OPTIONS, $content, $expected);
+    };
+}
+
+macro_rules! test_single_line_macros {
+    ($title: ident, $content: literal, $expected: literal ) => {
+        test_stable_with_options!($title, SINGLE_LINE_MACROS_OPTIONS, $content, $expected);
+    };
+}
+
+macro_rules! test_doc_comments {
+    ($title: ident, $content: literal, $expected: literal ) => {
+        test_stable_with_options!($title, DOC_COMMENTS_OPTIONS, $content, $expected);
+    };
+}
+
+macro_rules! test_fragment {
+    ($title: ident, $content: literal, $expected: literal ) => {
+        #[test]
+        fn $title() {
+            // check formatter works as expected, without leaving code unformatted
+            let (formatted, diagnostics) =
+                crate::count_diagnostics(|| crate::try_fmt_fragment($content, 0, &DEFAULT_OPTIONS));
+            pretty_assertions::assert_eq!(
+                formatted.expect("should be able to parse"),
+                String::from($expected)
+            );
+            assert_eq!(diagnostics, 0, "formatting reported diagnostics");
+            // check that `$expected` is valid maud markup
+            let (formatted, diagnostics) = crate::count_diagnostics(|| {
+                crate::try_fmt_fragment($expected, 0, &DEFAULT_OPTIONS)
+            });
+            formatted.expect("expected should be parsable");
+            assert_eq!(diagnostics, 0, "expected should be valid maud");
+        }
+    };
+}
+
+macro_rules! test_markdown {
     ($title: ident, $content: literal, $expected: literal ) => {
         #[test]
         fn $title() {
             // check formatter works as expected
             pretty_assertions::assert_eq!(
-                crate::try_fmt_file($content, &SMALL_LINE_OPTIONS)
+                crate::try_fmt_markdown($content, &DEFAULT_OPTIONS)
                     .expect("should be able to parse"),
                 String::from($expected)
             );
-            // check that `$expected` is a valid maud macro
-            crate::try_fmt_file($expected, &SMALL_LINE_OPTIONS)
-                .expect("expected should be parsable and valid maud");
+            // check that `$expected` is already formatted
+            pretty_assertions::assert_eq!(
+                crate::try_fmt_markdown($expected, &DEFAULT_OPTIONS)
+                    .expect("expected should be parsable"),
+                String::from($expected)
+            );
         }
     };
 }
 
 pub(crate) use test_default;
+pub(crate) use test_doc_comments;
+pub(crate) use test_fragment;
+pub(crate) use test_markdown;
+pub(crate) use test_single_line_macros;
 pub(crate) use test_small_line;
+pub(crate) use test_stable_with_options;
+pub(crate) use test_with_options;
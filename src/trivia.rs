@@ -0,0 +1,411 @@
+//! Comments and blank lines of maud markup. syn drops them, so they are collected from the
+//! source between the tokens of the markup and attached to the tokens around them: a node's
+//! leading trivia is the one of its first token and its trailing comment the one of its last.
+//!
+//! The trivia is kept in a side table keyed by token position rather than in the AST nodes: the
+//! AST is the one vendored from maud, parsed from a `TokenStream`, and every node already knows
+//! the spans of its tokens, so the printer looks its trivia up instead of carrying it around.
+
+use std::collections::HashMap;
+
+use crop::Rope;
+use proc_macro2::{Delimiter, LineColumn, TokenStream, TokenTree};
+use syn::Macro;
+
+use crate::format::line_column_to_byte;
+
+/// A `//` or `/* */` comment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Comment {
+    /// The comment as written, without trailing whitespace. The lines after the first one of a
+    /// block comment are indented relative to the line the comment starts on.
+    pub text: String,
+    pub start: LineColumn,
+}
+
+impl Comment {
+    /// Text after the `//` of a line comment
+    pub fn line_text(&self) -> Option<&str> {
+        self.text.strip_prefix("//")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Trivia {
+    Comment(Comment),
+    /// Number of consecutive empty lines
+    BlankLines(usize),
+}
+
+/// Comments and blank lines before a token
+#[derive(Debug, Default)]
+pub struct Leading {
+    /// The token is the first of its line
+    pub starts_line: bool,
+    /// On the lines above the token
+    pub trivia: Vec<Trivia>,
+    /// Block comments right before the token, on the same line
+    pub inline: Vec<Comment>,
+}
+
+/// Trivia of a macro body or a fragment, by token position
+#[derive(Debug, Default)]
+pub struct TriviaMap {
+    /// by token start
+    leading: HashMap<LineColumn, Leading>,
+    /// comments at the end of the line of a token, by token end
+    trailing: HashMap<LineColumn, Comment>,
+    /// every comment, in source order
+    comments: Vec<Comment>,
+}
+
+impl TriviaMap {
+    /// Trivia of the body of `mac`, its delimiters included
+    pub fn from_macro(source: &Rope, mac: &Macro) -> TriviaMap {
+        let delimiters = mac.delimiter.span();
+        let mut spans = vec![(delimiters.open().start(), delimiters.open().end())];
+        token_spans(mac.tokens.clone(), &mut spans);
+        spans.push((delimiters.close().start(), delimiters.close().end()));
+
+        TriviaMap::new(source, None, &spans, None)
+    }
+
+    /// Trivia of a whole source made of `tokens`, the end of the source counts as a token
+    pub fn from_fragment(source: &Rope, tokens: TokenStream) -> TriviaMap {
+        let mut spans = Vec::new();
+        token_spans(tokens, &mut spans);
+        let start = LineColumn { line: 1, column: 0 };
+
+        TriviaMap::new(source, Some(start), &spans, Some(source_end(source)))
+    }
+
+    /// `start` and `end` are where the source starts and ends when it isn't at a token
+    fn new(
+        source: &Rope,
+        start: Option<LineColumn>,
+        spans: &[(LineColumn, LineColumn)],
+        end: Option<LineColumn>,
+    ) -> Self {
+        let mut map = TriviaMap::default();
+        let mut previous = start.map(|start| (None, start));
+        let end = end.map(|end| (end, end));
+        for (idx, &(token_start, token_end)) in spans.iter().chain(&end).enumerate() {
+            if let Some((previous_end, gap_start)) = previous {
+                let start_byte = line_column_to_byte(source, gap_start);
+                let end_byte = line_column_to_byte(source, token_start);
+                // tokens of doc comments overlap each other
+                if start_byte <= end_byte {
+                    let gap = source.byte_slice(start_byte..end_byte).to_string();
+                    let pieces = scan_gap(source, &gap, gap_start);
+                    let at_end = end.is_some() && idx == spans.len();
+                    map.attach(pieces, previous_end, token_start, at_end);
+                }
+            }
+            previous = Some((Some(token_end), token_end));
+        }
+        map
+    }
+
+    /// Comments on the line of the previous token trail it, the others lead the next token or the
+    /// end of the source
+    fn attach(
+        &mut self,
+        pieces: Vec<GapPiece>,
+        previous_end: Option<LineColumn>,
+        next: LineColumn,
+        at_end: bool,
+    ) {
+        let last_line = pieces
+            .iter()
+            .map(|piece| match piece {
+                GapPiece::Newline => 1,
+                GapPiece::Comment(_, lines) => *lines,
+            })
+            .sum();
+        let mut leading = Leading {
+            starts_line: previous_end.is_none() || last_line > 0,
+            ..Default::default()
+        };
+        let mut line = 0;
+        let mut line_is_empty = previous_end.is_none();
+        let mut blank_lines = 0;
+
+        for piece in pieces {
+            match piece {
+                GapPiece::Newline => {
+                    if line_is_empty {
+                        blank_lines += 1;
+                    }
+                    line += 1;
+                    line_is_empty = true;
+                }
+                GapPiece::Comment(comment, lines) => {
+                    self.comments.push(comment.clone());
+                    line_is_empty = false;
+                    let (first_line, end_line) = (line, line + lines);
+                    line = end_line;
+
+                    match previous_end {
+                        Some(previous_end)
+                            if first_line == 0 && (end_line < last_line || at_end) =>
+                        {
+                            self.trailing
+                                .entry(previous_end)
+                                .and_modify(|trailing| {
+                                    trailing.text = format!("{} {}", trailing.text, comment.text)
+                                })
+                                .or_insert(comment);
+                        }
+                        _ if end_line == last_line => leading.inline.push(comment),
+                        _ => {
+                            if blank_lines > 0
+                                && (previous_end.is_some() || !leading.trivia.is_empty())
+                            {
+                                leading.trivia.push(Trivia::BlankLines(blank_lines));
+                            }
+                            blank_lines = 0;
+                            leading.trivia.push(Trivia::Comment(comment));
+                        }
+                    }
+                }
+            }
+        }
+        // blank lines at the start of a fragment are dropped
+        if blank_lines > 0 && (previous_end.is_some() || !leading.trivia.is_empty()) {
+            leading.trivia.push(Trivia::BlankLines(blank_lines));
+        }
+        self.leading.insert(next, leading);
+    }
+
+    /// Trivia above the token starting at `loc`
+    pub fn leading(&self, loc: LineColumn) -> Option<&Leading> {
+        self.leading.get(&loc)
+    }
+
+    /// Comment following the token ending at `loc`, on the same line
+    pub fn trailing(&self, loc: LineColumn) -> Option<&Comment> {
+        self.trailing.get(&loc)
+    }
+
+    pub fn comments(&self) -> &[Comment] {
+        &self.comments
+    }
+
+    /// Comments between `start` and `end`
+    pub fn comments_between(
+        &self,
+        start: LineColumn,
+        end: LineColumn,
+    ) -> impl Iterator<Item = &Comment> {
+        self.comments
+            .iter()
+            .filter(move |comment| start <= comment.start && comment.start < end)
+    }
+}
+
+/// Position of the end of `source`, which counts as a token in fragments
+pub fn source_end(source: &Rope) -> LineColumn {
+    LineColumn {
+        line: source.line_len().max(1),
+        column: source
+            .lines()
+            .next_back()
+            .map_or(0, |line| line.chars().count()),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum GapPiece {
+    Newline,
+    /// A comment and the number of line breaks inside it
+    Comment(Comment, usize),
+}
+
+/// Split the whitespace and comments between two tokens, starting at `pos`
+fn scan_gap(source: &Rope, gap: &str, mut pos: LineColumn) -> Vec<GapPiece> {
+    let mut pieces = Vec::new();
+    let mut idx = 0;
+    while let Some(c) = gap[idx..].chars().next() {
+        let rest = &gap[idx..];
+        let len = if c == '\n' {
+            pieces.push(GapPiece::Newline);
+            pos = LineColumn {
+                line: pos.line + 1,
+                column: 0,
+            };
+            idx += 1;
+            continue;
+        } else if rest.starts_with("//") {
+            rest.find('\n').unwrap_or(rest.len())
+        } else if rest.starts_with("/*") {
+            block_comment_len(rest)
+        } else {
+            pos.column += 1;
+            idx += c.len_utf8();
+            continue;
+        };
+
+        let text = &rest[..len];
+        let comment = Comment {
+            text: reindent_block_comment(text.trim_end(), line_indent(source, pos.line)),
+            start: pos,
+        };
+        pieces.push(GapPiece::Comment(comment, text.matches('\n').count()));
+
+        pos = match text.rsplit_once('\n') {
+            Some((_, last_line)) => LineColumn {
+                line: pos.line + text.matches('\n').count(),
+                column: last_line.chars().count(),
+            },
+            None => LineColumn {
+                line: pos.line,
+                column: pos.column + text.chars().count(),
+            },
+        };
+        idx += len;
+    }
+    pieces
+}
+
+/// Remove the indentation of the line a block comment starts on from its next lines
+fn reindent_block_comment(comment: &str, line_indent: usize) -> String {
+    let mut lines = comment.split('\n');
+    let mut text = lines.next().unwrap_or_default().to_string();
+    for line in lines {
+        text.push('\n');
+        text.push_str(dedent(line, line_indent).trim_end());
+    }
+    text
+}
+
+/// Number of indentation characters of `line` (1-based)
+pub fn line_indent(source: &Rope, line: usize) -> usize {
+    source
+        .line(line - 1)
+        .chars()
+        .take_while(|c| *c == ' ' || *c == '\t')
+        .count()
+}
+
+/// Remove up to `indent` indentation characters from `line`
+pub fn dedent(line: &str, indent: usize) -> &str {
+    let len = line
+        .chars()
+        .take(indent)
+        .take_while(|c| *c == ' ' || *c == '\t')
+        .map(char::len_utf8)
+        .sum::<usize>();
+    &line[len..]
+}
+
+fn token_spans(tokens: TokenStream, spans: &mut Vec<(LineColumn, LineColumn)>) {
+    for token in tokens {
+        match token {
+            TokenTree::Group(group) if group.delimiter() == Delimiter::None => {
+                token_spans(group.stream(), spans);
+            }
+            TokenTree::Group(group) => {
+                let (open, close) = (group.span_open(), group.span_close());
+                spans.push((open.start(), open.end()));
+                token_spans(group.stream(), spans);
+                spans.push((close.start(), close.end()));
+            }
+            token => spans.push((token.span().start(), token.span().end())),
+        }
+    }
+}
+
+/// Length of the (possibly nested) block comment `comment` starts with
+fn block_comment_len(comment: &str) -> usize {
+    let mut depth = 0;
+    let mut idx = 0;
+    while idx < comment.len() {
+        let rest = &comment[idx..];
+        if rest.starts_with("/*") {
+            depth += 1;
+            idx += 2;
+        } else if rest.starts_with("*/") {
+            depth -= 1;
+            idx += 2;
+            if depth == 0 {
+                return idx;
+            }
+        } else {
+            idx += rest.chars().next().map_or(1, char::len_utf8);
+        }
+    }
+    comment.len()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fragment_trivia(source: &str) -> TriviaMap {
+        TriviaMap::from_fragment(&Rope::from(source), source.parse().unwrap())
+    }
+
+    fn comment(text: &str, line: usize, column: usize) -> Comment {
+        Comment {
+            text: text.to_string(),
+            start: LineColumn { line, column },
+        }
+    }
+
+    #[test]
+    fn leading_and_trailing_comments() {
+        let trivia = fragment_trivia("a  // after a\n// above b\n\n\n// right above b\nb");
+
+        assert_eq!(
+            trivia.trailing(LineColumn { line: 1, column: 1 }),
+            Some(&comment("// after a", 1, 3))
+        );
+        let leading = trivia.leading(LineColumn { line: 6, column: 0 }).unwrap();
+        assert!(leading.starts_line);
+        assert_eq!(
+            leading.trivia,
+            vec![
+                Trivia::Comment(comment("// above b", 2, 0)),
+                Trivia::BlankLines(2),
+                Trivia::Comment(comment("// right above b", 5, 0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn slashes_in_literals_are_not_comments() {
+        let trivia = fragment_trivia("a href=\"https://example.org\" { \"//\" }");
+
+        assert_eq!(trivia.comments.len(), 0);
+    }
+
+    #[test]
+    fn block_comments() {
+        let trivia = fragment_trivia(
+            "a /* // */ b
+    /* one
+       two */
+    c /* d */
+",
+        );
+
+        let b = trivia
+            .leading(LineColumn {
+                line: 1,
+                column: 11,
+            })
+            .unwrap();
+        assert!(!b.starts_line);
+        assert_eq!(b.inline, vec![comment("/* // */", 1, 2)]);
+
+        let c = trivia.leading(LineColumn { line: 4, column: 4 }).unwrap();
+        assert_eq!(
+            c.trivia,
+            vec![Trivia::Comment(comment("/* one\n   two */", 2, 4))]
+        );
+        assert_eq!(
+            trivia.trailing(LineColumn { line: 4, column: 5 }),
+            Some(&comment("/* d */", 4, 6))
+        );
+    }
+}
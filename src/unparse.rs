@@ -1,61 +1,75 @@
+use anyhow::{Context, Result, bail};
 use proc_macro2::TokenStream;
 use quote::quote;
-use syn::{Expr, File, Item, Local, Pat, Stmt};
+use syn::{Expr, File, Item, Pat, Stmt};
 
-pub fn unparse_pat(pat: &Pat, total_indent_size: usize) -> Vec<String> {
-    let tokens = quote!(let #pat;);
-    let unparsed = unparse(tokens, total_indent_size);
+use crate::vendor::{
+    ast::{HtmlName, HtmlNameFragment, HtmlNamePunct},
+    prettyplease::unparse_with_margin,
+};
 
-    match unparsed.len() {
-        0 => unparsed,
-        1 => vec![
-            unparsed[0]
-                .trim()
-                .strip_prefix("let ")
-                .unwrap()
-                .strip_suffix(";")
-                .unwrap()
-                .to_string(),
-        ],
-        _ => {
-            let mut unparsed = unparsed;
-            unparsed[0] = unparsed[0].trim().strip_prefix("let ").unwrap().to_string();
-            let last_idx = unparsed.len();
-            unparsed[last_idx] = unparsed[last_idx].strip_suffix(";").unwrap().to_string();
+pub fn unparse_pat(pat: &Pat, total_indent_size: usize, width: usize) -> Result<Vec<String>> {
+    let tokens = quote!(let #pat;);
+    let mut unparsed = unparse(tokens, total_indent_size, width)?;
 
-            unparsed
-        }
-    }
+    let first = unparsed.first_mut().context("empty pattern")?;
+    *first = first
+        .trim_start()
+        .strip_prefix("let ")
+        .context("pattern without `let`")?
+        .to_string();
+    let last = unparsed.last_mut().context("empty pattern")?;
+    *last = last
+        .strip_suffix(';')
+        .context("pattern without `;`")?
+        .to_string();
+    Ok(unparsed)
 }
 
-pub fn unparse_local(local: &Local, total_indent_size: usize) -> Vec<String> {
-    let tokens = quote!(#local;);
-    let unparsed = unparse(tokens, total_indent_size);
-
-    match unparsed.len() {
-        0 => unparsed,
-        1 => vec![unparsed[0].trim().strip_suffix(";").unwrap().to_string()],
-        _ => {
-            let mut unparsed = unparsed;
-            let last_idx = unparsed.len() - 1;
-            unparsed[last_idx] = unparsed[last_idx].strip_suffix(";").unwrap().to_string();
+pub fn unparse_expr(expr: &Expr, total_indent_size: usize, width: usize) -> Result<Vec<String>> {
+    let tokens = quote!(#expr);
+    unparse(tokens, total_indent_size, width)
+}
 
-            unparsed
+/// Name of an element, id or class
+pub fn unparse_html_name(name: &HtmlName) -> String {
+    let mut unparsed = String::new();
+    for child in name.name.pairs() {
+        match child.value() {
+            HtmlNameFragment::LitStr(lit) => unparsed += &quote!(#lit).to_string(),
+            value => unparsed += &value.to_string(),
+        }
+        if let Some(punct) = child.punct() {
+            match punct {
+                HtmlNamePunct::Hyphen(_) => unparsed += "-",
+                HtmlNamePunct::Colon(_) => unparsed += ":",
+            }
         }
     }
+    unparsed
 }
 
-pub fn unparse_expr(expr: &Expr, total_indent_size: usize) -> Vec<String> {
-    let tokens = quote!(#expr);
-    unparse(tokens, total_indent_size)
+/// Name of an attribute, quoted when it isn't a valid maud name
+pub fn unparse_html_attribute_name(name: &HtmlName) -> String {
+    let value = name.to_string();
+    if value.contains('@') || value.contains('.') || value.starts_with(":") {
+        quote!(#value).to_string()
+    } else {
+        value
+    }
 }
 
-pub fn unparse_stmts(stmts: &Vec<Stmt>, total_indent_size: usize) -> Vec<String> {
+pub fn unparse_stmts(
+    stmts: &[Stmt],
+    total_indent_size: usize,
+    width: usize,
+) -> Result<Vec<String>> {
     let tokens = quote!(#(#stmts)*);
-    unparse(tokens, total_indent_size)
+    unparse(tokens, total_indent_size, width)
 }
 
-fn unparse(tokens: TokenStream, total_indent_size: usize) -> Vec<String> {
+/// Lines of `tokens` in a block nested `total_indent_size` times, for lines of `width` columns
+fn unparse(tokens: TokenStream, total_indent_size: usize, width: usize) -> Result<Vec<String>> {
     let mut indented_tokens = tokens;
     for _ in 0..total_indent_size {
         indented_tokens = quote! {
@@ -79,17 +93,25 @@ fn unparse(tokens: TokenStream, total_indent_size: usize) -> Vec<String> {
         ],
     };
 
-    let wrapped = prettyplease::unparse(&file);
+    let wrapped = unparse_with_margin(&file, width as isize);
 
-    let indented_unwrapped = wrapped
+    let lines: Vec<&str> = wrapped
         .strip_prefix("fn main() {\n")
-        .unwrap()
-        .strip_suffix("}\n")
-        .unwrap()
+        .and_then(|wrapped| wrapped.strip_suffix("}\n"))
+        .context("unexpected prettyplease output")?
         .lines()
-        .map(|line| line.to_string())
-        .collect::<Vec<_>>();
+        .collect();
 
-    indented_unwrapped[(2 * total_indent_size)..(indented_unwrapped.len() - total_indent_size)]
-        .to_vec()
+    // the block and doc comment lines around the code
+    let (start, end) = (
+        2 * total_indent_size,
+        lines.len().saturating_sub(total_indent_size),
+    );
+    if start > end {
+        bail!("unexpected prettyplease output");
+    }
+    Ok(lines[start..end]
+        .iter()
+        .map(|line| line.to_string())
+        .collect())
 }
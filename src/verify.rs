@@ -0,0 +1,185 @@
+//! Idempotency check: formatting a formatted source must not change it.
+
+use std::{fmt, path::Path};
+
+use anyhow::Result;
+use crop::Rope;
+use syn::spanned::Spanned as _;
+
+use crate::{
+    collect,
+    format::{self, FormatOptions, line_column_to_byte},
+    newline::SourceStyle,
+};
+
+/// A macro (or a whole input) formatted differently by a second pass
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unstable {
+    /// 1-based position of the macro in the original source
+    pub line: usize,
+    pub column: usize,
+    /// Output of the first pass
+    pub first: String,
+    /// Output of the second pass, formatting `first`
+    pub second: String,
+}
+
+impl Unstable {
+    /// Line diff from the first pass to the second one
+    pub fn diff(&self) -> String {
+        line_diff(&self.first, &self.second)
+    }
+}
+
+impl fmt::Display for Unstable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: formatting again changes the output\n{}",
+            self.line,
+            self.column,
+            self.diff()
+        )
+    }
+}
+
+/// Format `source` twice and return the macros whose second pass differs from the first one
+pub fn verify_file(
+    source: &str,
+    path: Option<&Path>,
+    options: &FormatOptions,
+) -> Result<Vec<Unstable>> {
+    let (_, source) = SourceStyle::normalize(source, options.newline_style);
+    let first = crate::fmt_file(&source, path, options)?;
+    let second = crate::fmt_file(&first, path, options)?;
+    if first == second {
+        return Ok(Vec::new());
+    }
+
+    let unstable = match (
+        macro_texts(&source, options),
+        macro_texts(&first, options),
+        macro_texts(&second, options),
+    ) {
+        (Some(original), Some(first), Some(second))
+            if original.len() == first.len() && first.len() == second.len() =>
+        {
+            original
+                .into_iter()
+                .zip(first.into_iter().zip(second))
+                .filter(|(_, (first, second))| first.1 != second.1)
+                .map(|((start, _), (first, second))| Unstable {
+                    line: start.line,
+                    column: start.column + 1,
+                    first: first.1,
+                    second: second.1,
+                })
+                .collect()
+        }
+        _ => Vec::new(),
+    };
+
+    // the difference is outside of the macros (doc comments), or they can't be matched
+    if unstable.is_empty() {
+        return Ok(vec![Unstable {
+            line: 1,
+            column: 1,
+            first,
+            second,
+        }]);
+    }
+    Ok(unstable)
+}
+
+/// Position and text of the macros of `source`, `None` if it can't be parsed
+fn macro_texts(
+    source: &str,
+    options: &FormatOptions,
+) -> Option<Vec<(proc_macro2::LineColumn, String)>> {
+    let (source, _) = format::preprocess_source_for_ignore(source);
+    let file = syn::parse_file(&source).ok()?;
+    let (rope, macros, _) =
+        collect::collect_macros_from_file(&file, Rope::from(source), &options.macro_names);
+
+    let texts = macros
+        .iter()
+        .map(|mac| {
+            let start = mac.macro_.path.span().start();
+            let end = mac.macro_.delimiter.span().close().end();
+            let range = line_column_to_byte(&rope, start)..line_column_to_byte(&rope, end);
+            (start, rope.byte_slice(range).to_string())
+        })
+        .collect();
+    Some(texts)
+}
+
+/// Lines removed from `old` prefixed by `-`, lines added by `new` by `+`, common lines by a space
+pub fn line_diff(old: &str, new: &str) -> String {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    // longest common subsequence lengths of the suffixes
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            diff += &format!(" {}\n", old[i]);
+            (i, j) = (i + 1, j + 1);
+        } else if j == new.len() || (i < old.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            diff += &format!("-{}\n", old[i]);
+            i += 1;
+        } else {
+            diff += &format!("+{}\n", new[j]);
+            j += 1;
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn diff_lines() {
+        assert_eq!(line_diff("a\nb\nc", "a\nc\nd"), " a\n-b\n c\n+d\n");
+        assert_eq!(line_diff("a", "a"), " a\n");
+    }
+
+    #[test]
+    fn unstable_display() {
+        let unstable = Unstable {
+            line: 3,
+            column: 5,
+            first: String::from("html! {\n\n    p {}\n}"),
+            second: String::from("html! {\n    p {}\n}"),
+        };
+
+        assert_eq!(
+            unstable.to_string(),
+            "3:5: formatting again changes the output\n html! {\n-\n     p {}\n }\n"
+        );
+    }
+
+    #[test]
+    fn stable_file() {
+        let source = "html! {\n    p { \"a\" }\n}\n";
+
+        assert!(
+            verify_file(source, None, &FormatOptions::default())
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
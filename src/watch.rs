@@ -0,0 +1,147 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::mpsc,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use maudfmt::{FormatOptions, rustfmt::Rustfmt};
+use notify_debouncer_mini::{DebounceEventResult, new_debouncer, notify::RecursiveMode};
+
+use crate::{InputMode, format_input, get_file_paths};
+
+/// Editors often save a file in several steps, wait for them to settle
+const DEBOUNCE_DELAY: Duration = Duration::from_millis(200);
+
+/// Format the files selected by `patterns` every time they are saved, until interrupted
+pub fn watch(
+    patterns: Vec<String>,
+    options: &FormatOptions,
+    rustfmt: Option<&Rustfmt>,
+) -> Result<()> {
+    let (tx, rx) = mpsc::channel::<DebounceEventResult>();
+    let mut debouncer = new_debouncer(DEBOUNCE_DELAY, tx).context("Failed to start watching")?;
+    for (dir, mode) in watched_dirs(&patterns) {
+        debouncer
+            .watcher()
+            .watch(&dir, mode)
+            .with_context(|| format!("Failed to watch {}", dir.display()))?;
+    }
+    println!("Watching for changes...");
+
+    // content of the files we wrote, so that our own writes don't trigger a new format
+    let mut written: HashMap<PathBuf, String> = HashMap::new();
+
+    for result in rx {
+        let events = match result {
+            Ok(events) => events,
+            Err(err) => {
+                eprintln!("Watch error: {err}");
+                continue;
+            }
+        };
+
+        // selection is done again each time, to pick up new files
+        let selected = match get_file_paths(patterns.clone()) {
+            Ok(paths) => paths
+                .into_iter()
+                .filter_map(|path| path.canonicalize().ok().map(|canonical| (canonical, path)))
+                .collect::<HashMap<_, _>>(),
+            Err(err) => {
+                eprintln!("{err:#}");
+                continue;
+            }
+        };
+
+        let changed: HashSet<PathBuf> = events
+            .into_iter()
+            .filter_map(|event| event.path.canonicalize().ok())
+            .collect();
+        for canonical in changed {
+            let Some(path) = selected.get(&canonical) else {
+                continue;
+            };
+            match format_file(path, options, rustfmt, written.get(&canonical)) {
+                Ok(Some(formatted)) => {
+                    println!("Formatted {}", path.display());
+                    written.insert(canonical, formatted);
+                }
+                Ok(None) => {}
+                // keep watching, the file will likely be fixed and saved again
+                Err(err) => eprintln!("{}: {err:#}", path.display()),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the new content if the file was rewritten
+fn format_file(
+    path: &Path,
+    options: &FormatOptions,
+    rustfmt: Option<&Rustfmt>,
+    last_written: Option<&String>,
+) -> Result<Option<String>> {
+    let source = fs::read_to_string(path)?;
+    if last_written == Some(&source) {
+        return Ok(None);
+    }
+
+    let mode = InputMode::from_path(path);
+    let (formatted, _) = format_input(&source, Some(path), mode, options, rustfmt)?;
+    if formatted == source {
+        return Ok(None);
+    }
+
+    fs::write(path, &formatted)?;
+    Ok(Some(formatted))
+}
+
+/// Directories to watch for the file, directory or glob `patterns`
+fn watched_dirs(patterns: &[String]) -> Vec<(PathBuf, RecursiveMode)> {
+    let mut dirs: Vec<(PathBuf, RecursiveMode)> = patterns
+        .iter()
+        .map(|pattern| {
+            // the part of a glob before the first wildcard
+            let base: PathBuf = Path::new(pattern)
+                .components()
+                .take_while(|component| {
+                    !component
+                        .as_os_str()
+                        .to_string_lossy()
+                        .contains(['*', '?', '[', '{'])
+                })
+                .collect();
+            let base = if base.as_os_str().is_empty() {
+                PathBuf::from(".")
+            } else {
+                base
+            };
+
+            if base.is_file() {
+                // watch the parent, editors often replace files instead of writing them
+                let parent = base
+                    .parent()
+                    .filter(|parent| !parent.as_os_str().is_empty())
+                    .unwrap_or(Path::new("."));
+                (parent.to_path_buf(), RecursiveMode::NonRecursive)
+            } else {
+                (base, RecursiveMode::Recursive)
+            }
+        })
+        .collect();
+    dirs.sort_by(|(a, _), (b, _)| a.cmp(b));
+    dirs.dedup_by(|(dir, mode), (kept_dir, kept_mode)| {
+        if dir != kept_dir {
+            return false;
+        }
+        if *mode == RecursiveMode::Recursive {
+            *kept_mode = RecursiveMode::Recursive;
+        }
+        true
+    });
+    dirs
+}
@@ -0,0 +1,209 @@
+use anyhow::Result;
+use assert_cmd::Command;
+use assert_fs::{TempDir, prelude::*};
+use predicates::prelude::*;
+
+static IN_FILE: &str = "fn footer() -> Markup {\n    html!{footer{\"RSS Feed\"}}\n}\n";
+
+static OUT_FILE: &str =
+    "fn footer() -> Markup {\n    html! {\n        footer { \"RSS Feed\" }\n    }\n}\n";
+
+/// Files of the targets of a package and the modules they declare before `IN_FILE`
+static TARGET_FILES: &[(&str, &str)] = &[
+    ("src/lib.rs", "mod components;\n"),
+    ("src/components.rs", "mod footer;\n"),
+    ("src/components/footer.rs", ""),
+    ("src/bin/cli.rs", ""),
+    ("tests/it.rs", ""),
+    ("examples/example.rs", ""),
+    ("benches/bench.rs", ""),
+    ("build.rs", "#[path = \"build/helpers.rs\"]\nmod helpers;\n"),
+    ("build/helpers.rs", ""),
+];
+
+/// Files that are not rust modules of a target
+static OTHER_FILES: &[(&str, &str)] = &[
+    (
+        "scripts/gen.rs",
+        "fn footer() -> Markup {\n    html!{footer{\"RSS Feed\"}}\n}\n",
+    ),
+    (
+        "src/orphan.rs",
+        "fn footer() -> Markup {\n    html!{footer{\"RSS Feed\"}}\n}\n",
+    ),
+    ("src/nav.maud", "nav{\"Home\"}\n"),
+    ("src/README.md", "```rust\nhtml!{p{\"Hello\"}}\n```\n"),
+];
+
+/// Workspace with packages `a` and `b`, every source file contains `IN_FILE`
+fn workspace() -> Result<TempDir> {
+    let dir = TempDir::new()?;
+    dir.child("Cargo.toml")
+        .write_str("[workspace]\nmembers = [\"a\", \"b\"]\nresolver = \"2\"\n")?;
+    for package in ["a", "b"] {
+        dir.child(format!("{package}/Cargo.toml"))
+            .write_str(&format!(
+                "[package]\nname = \"{package}\"\nversion = \"0.1.0\"\nedition = \"2021\"\n"
+            ))?;
+        for (file, modules) in TARGET_FILES {
+            dir.child(format!("{package}/{file}"))
+                .write_str(&format!("{modules}{IN_FILE}"))?;
+        }
+        for (file, content) in OTHER_FILES {
+            dir.child(format!("{package}/{file}")).write_str(content)?;
+        }
+    }
+    Ok(dir)
+}
+
+fn assert_package(dir: &TempDir, package: &str, formatted: bool) {
+    let expected = if formatted { OUT_FILE } else { IN_FILE };
+    for (file, modules) in TARGET_FILES {
+        dir.child(format!("{package}/{file}"))
+            .assert(format!("{modules}{expected}"));
+    }
+    for (file, content) in OTHER_FILES {
+        dir.child(format!("{package}/{file}")).assert(*content);
+    }
+}
+
+#[test]
+fn format_current_package() -> Result<()> {
+    // Given
+    let dir = workspace()?;
+
+    // When
+    let mut cmd = Command::cargo_bin("cargo-maudfmt")?;
+    cmd.current_dir(dir.child("a").path()).arg("maudfmt");
+
+    // Then
+    cmd.assert().success();
+    assert_package(&dir, "a", true);
+    assert_package(&dir, "b", false);
+
+    Ok(())
+}
+
+#[test]
+fn format_all_packages() -> Result<()> {
+    // Given
+    let dir = workspace()?;
+
+    // When
+    let mut cmd = Command::cargo_bin("cargo-maudfmt")?;
+    cmd.current_dir(dir.child("a").path())
+        .args(["maudfmt", "--all"]);
+
+    // Then
+    cmd.assert().success();
+    assert_package(&dir, "a", true);
+    assert_package(&dir, "b", true);
+
+    Ok(())
+}
+
+#[test]
+fn format_selected_package() -> Result<()> {
+    // Given
+    let dir = workspace()?;
+
+    // When
+    let mut cmd = Command::cargo_bin("cargo-maudfmt")?;
+    cmd.current_dir(dir.path()).args(["maudfmt", "-p", "b"]);
+
+    // Then
+    cmd.assert().success();
+    assert_package(&dir, "a", false);
+    assert_package(&dir, "b", true);
+
+    Ok(())
+}
+
+#[test]
+fn format_unknown_package() -> Result<()> {
+    let dir = workspace()?;
+
+    let mut cmd = Command::cargo_bin("cargo-maudfmt")?;
+    cmd.current_dir(dir.path()).args(["maudfmt", "-p", "c"]);
+
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "Package `c` is not a member of the workspace",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn check_packages() -> Result<()> {
+    // Given
+    let dir = workspace()?;
+
+    // When
+    let mut cmd = Command::cargo_bin("cargo-maudfmt")?;
+    cmd.current_dir(dir.path())
+        .args(["maudfmt", "--all", "--check"]);
+
+    // Then
+    cmd.assert()
+        .failure()
+        .stdout(predicate::str::contains("Would reformat").count(18));
+    assert_package(&dir, "a", false);
+    assert_package(&dir, "b", false);
+
+    // Once formatted
+    let mut cmd = Command::cargo_bin("cargo-maudfmt")?;
+    cmd.current_dir(dir.path()).args(["maudfmt", "--all"]);
+    cmd.assert().success();
+
+    let mut cmd = Command::cargo_bin("cargo-maudfmt")?;
+    cmd.current_dir(dir.path())
+        .args(["maudfmt", "--all", "--check"]);
+    cmd.assert().success().stdout("");
+
+    Ok(())
+}
+
+#[test]
+fn check_unparsable_file() -> Result<()> {
+    // Given
+    let dir = workspace()?;
+    let mut cmd = Command::cargo_bin("cargo-maudfmt")?;
+    cmd.current_dir(dir.path()).args(["maudfmt", "--all"]);
+    cmd.assert().success();
+    dir.child("a/src/components/footer.rs")
+        .write_str("fn footer( { html!{footer{\"RSS Feed\"}} }\n")?;
+
+    // When
+    let mut cmd = Command::cargo_bin("cargo-maudfmt")?;
+    cmd.current_dir(dir.path())
+        .args(["maudfmt", "--all", "--check"]);
+
+    // Then
+    cmd.assert()
+        .failure()
+        .stdout("")
+        .stderr(predicate::str::contains(
+            "footer.rs: Failed to parse source",
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn forward_maudfmt_options() -> Result<()> {
+    // Given
+    let dir = workspace()?;
+
+    // When
+    let mut cmd = Command::cargo_bin("cargo-maudfmt")?;
+    cmd.current_dir(dir.path())
+        .args(["maudfmt", "-p", "a", "--", "--line-length", "20"]);
+
+    // Then
+    cmd.assert().success();
+    dir.child("a/src/lib.rs").assert(
+        "mod components;\nfn footer() -> Markup {\n    html! {\n        footer {\n            \"RSS Feed\"\n        }\n    }\n}\n",
+    );
+
+    Ok(())
+}
@@ -66,7 +66,7 @@ fn format_file_from_argument() -> Result<()> {
 
     // When
     let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
-    cmd.arg(file.path());
+    cmd.arg("--no-cache").arg(file.path());
 
     // Then
     cmd.assert().success();
@@ -85,7 +85,7 @@ fn format_multiple_files_from_argument() -> Result<()> {
 
     // When
     let mut cmd = Command::cargo_bin("maudfmt")?;
-    cmd.arg(file_1.path()).arg(file_2.path());
+    cmd.arg("--no-cache").arg(file_1.path()).arg(file_2.path());
 
     // Then
     cmd.assert().success();
@@ -106,7 +106,7 @@ fn format_dir_from_argument() -> Result<()> {
 
     // When
     let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
-    cmd.arg(directory.path());
+    cmd.arg("--no-cache").arg(directory.path());
 
     // Then
     cmd.assert().success();
@@ -194,7 +194,8 @@ fn format_file_with_custom_macro_names() -> Result<()> {
     file.write_str(CUSTOM_MACRO_IN_FILE)?;
 
     let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
-    cmd.arg("--macro-names")
+    cmd.arg("--no-cache")
+        .arg("--macro-names")
         .arg("maud,hyperscript::maud")
         .arg(file.path());
 
@@ -228,7 +229,10 @@ fn format_file_with_custom_macro_names_short_arg() -> Result<()> {
     file.write_str(CUSTOM_MACRO_IN_FILE)?;
 
     let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
-    cmd.arg("-m").arg("maud,hyperscript::maud").arg(file.path());
+    cmd.arg("--no-cache")
+        .arg("-m")
+        .arg("maud,hyperscript::maud")
+        .arg(file.path());
 
     cmd.assert().success();
     assert_eq!(std::fs::read_to_string(&file)?, CUSTOM_MACRO_OUT_FILE);
@@ -277,7 +281,10 @@ fn format_file_with_short_line_length() -> Result<()> {
     file.write_str(LONG_LINE_IN_FILE)?;
 
     let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
-    cmd.arg("--line-length").arg("50").arg(file.path());
+    cmd.arg("--no-cache")
+        .arg("--line-length")
+        .arg("50")
+        .arg(file.path());
 
     cmd.assert().success();
     assert_eq!(
@@ -294,7 +301,10 @@ fn format_file_with_long_line_length() -> Result<()> {
     file.write_str(LONG_LINE_IN_FILE)?;
 
     let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
-    cmd.arg("--line-length").arg("200").arg(file.path());
+    cmd.arg("--no-cache")
+        .arg("--line-length")
+        .arg("200")
+        .arg(file.path());
 
     cmd.assert().success();
     assert_eq!(
@@ -322,3 +332,546 @@ fn format_stdin_with_line_length() -> Result<()> {
 
     Ok(())
 }
+
+static DIRECTIVES_IN_FILE: &str = r#"
+use maud::{html, Markup};
+
+fn test() -> Markup {
+    html! {
+        ul {
+
+            // maudfmt: expand
+            li { "Expanded" }
+            // maudfmt: collapse
+            li {
+                a href="/" { "Collapsed" }
+            }
+            // a regular comment
+            li { "Untouched" }
+        }
+    }
+}
+"#;
+
+static DIRECTIVES_OUT_FILE: &str = r#"
+use maud::{html, Markup};
+
+fn test() -> Markup {
+    html! {
+        ul {
+
+            li {
+                "Expanded"
+            }
+            li { a href="/" { "Collapsed" } }
+            // a regular comment
+            li { "Untouched" }
+        }
+    }
+}
+"#;
+
+#[test]
+fn format_file_with_stripped_layout_directives() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("sample.rs")?;
+    file.write_str(DIRECTIVES_IN_FILE)?;
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--no-cache")
+        .arg("--strip-layout-directives")
+        .arg(file.path());
+
+    cmd.assert().success();
+    assert_eq!(std::fs::read_to_string(&file)?, DIRECTIVES_OUT_FILE);
+
+    Ok(())
+}
+
+static FRAGMENT_IN_FILE: &str = r#"nav{ul{li{a href="/"{"Home"}}}}
+p{"text"}
+"#;
+
+static FRAGMENT_OUT_FILE: &str = r#"nav {
+    ul {
+        li {
+            a href="/" { "Home" }
+        }
+    }
+}
+p { "text" }
+"#;
+
+#[test]
+fn format_maud_file_from_argument() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("snippet.maud")?;
+    file.write_str(FRAGMENT_IN_FILE)?;
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--no-cache").arg(file.path());
+
+    cmd.assert().success();
+    assert_eq!(std::fs::read_to_string(&file)?, FRAGMENT_OUT_FILE);
+
+    Ok(())
+}
+
+#[test]
+fn format_maud_file_from_dir_argument() -> Result<()> {
+    let directory = assert_fs::TempDir::new()?;
+    let file = directory.child("snippet.maud");
+    file.write_str(FRAGMENT_IN_FILE)?;
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--no-cache").arg(directory.path());
+
+    cmd.assert().success();
+    assert_eq!(std::fs::read_to_string(&file)?, FRAGMENT_OUT_FILE);
+
+    Ok(())
+}
+
+#[test]
+fn format_fragment_from_stdin_keeps_indent() -> Result<()> {
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("-s")
+        .arg("--fragment")
+        .write_stdin("        p{\"text\"}\n        br;\n");
+
+    cmd.assert().success().stdout(predicate::str::diff(
+        "        p { \"text\" }\n        br;\n",
+    ));
+
+    Ok(())
+}
+
+static MARKDOWN_IN_FILE: &str = r#"# Footer
+
+```rust
+fn footer() -> Markup {
+    html!{footer{a href="rss.atom"{"RSS Feed"}}}
+}
+```
+"#;
+
+static MARKDOWN_OUT_FILE: &str = r#"# Footer
+
+```rust
+fn footer() -> Markup {
+    html! {
+        footer {
+            a href="rss.atom" { "RSS Feed" }
+        }
+    }
+}
+```
+"#;
+
+#[test]
+fn format_markdown_file_from_argument() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("README.md")?;
+    file.write_str(MARKDOWN_IN_FILE)?;
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--no-cache").arg(file.path());
+
+    cmd.assert().success();
+    assert_eq!(std::fs::read_to_string(&file)?, MARKDOWN_OUT_FILE);
+
+    Ok(())
+}
+
+#[test]
+fn markdown_files_not_formatted_from_directory() -> Result<()> {
+    let directory = assert_fs::TempDir::new()?;
+    let readme = directory.child("README.md");
+    readme.write_str(MARKDOWN_IN_FILE)?;
+    let source = directory.child("lib.rs");
+    source.write_str(IN_FILE)?;
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--no-cache").arg(directory.path());
+
+    cmd.assert().success();
+    assert_eq!(std::fs::read_to_string(&readme)?, MARKDOWN_IN_FILE);
+    assert_eq!(std::fs::read_to_string(&source)?, OUT_FILE);
+
+    Ok(())
+}
+
+#[test]
+fn check_files() -> Result<()> {
+    // Given
+    let unformatted = assert_fs::NamedTempFile::new("unformatted.rs")?;
+    unformatted.write_str(IN_FILE)?;
+    let formatted = assert_fs::NamedTempFile::new("formatted.rs")?;
+    formatted.write_str(OUT_FILE)?;
+
+    // When
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--no-cache")
+        .arg("--check")
+        .arg(unformatted.path())
+        .arg(formatted.path());
+
+    // Then
+    cmd.assert()
+        .failure()
+        .stdout(
+            predicate::str::contains("Would reformat")
+                .and(predicate::str::contains("unformatted.rs")),
+        )
+        .stdout(predicate::str::contains("formatted.rs\n").count(1));
+    unformatted.assert(IN_FILE);
+
+    Ok(())
+}
+
+#[test]
+fn verify_files() -> Result<()> {
+    // Given
+    let file = assert_fs::NamedTempFile::new("sample.rs")?;
+    file.write_str(IN_FILE)?;
+
+    // When
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--verify").arg(file.path());
+
+    // Then
+    cmd.assert().success().stdout("");
+    file.assert(IN_FILE);
+
+    Ok(())
+}
+
+#[test]
+fn verify_stdin() -> Result<()> {
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(["--verify", "-s"]).write_stdin(IN_FILE);
+    cmd.assert().success().stdout("");
+
+    Ok(())
+}
+
+#[test]
+fn check_stdin() -> Result<()> {
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(["--check", "-s"]).write_stdin(OUT_FILE);
+    cmd.assert().success().stdout("");
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(["--check", "-s"]).write_stdin(IN_FILE);
+    cmd.assert()
+        .failure()
+        .stdout("")
+        .stderr(predicate::str::contains("stdin is not formatted"));
+
+    Ok(())
+}
+
+static UNPARSABLE_FILE: &str = "fn main( { html!{p{\"a\"}} }\n";
+
+#[test]
+fn unparsable_file_reported() -> Result<()> {
+    // Given
+    let file = assert_fs::NamedTempFile::new("unparsable.rs")?;
+    file.write_str(UNPARSABLE_FILE)?;
+
+    // When
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--no-cache").arg(file.path());
+
+    // Then
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "unparsable.rs: Failed to parse source",
+    ));
+    file.assert(UNPARSABLE_FILE);
+
+    Ok(())
+}
+
+#[test]
+fn check_fails_on_files_not_formatted() -> Result<()> {
+    // Given
+    let unparsable = assert_fs::NamedTempFile::new("unparsable.rs")?;
+    unparsable.write_str(UNPARSABLE_FILE)?;
+    let invalid_macro = assert_fs::NamedTempFile::new("invalid_macro.rs")?;
+    invalid_macro.write_str("fn main() {\n    html! { @if { } }\n}\n")?;
+
+    for file in [&unparsable, &invalid_macro] {
+        // When
+        let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+        cmd.arg("--no-cache").arg("--check").arg(file.path());
+
+        // Then
+        cmd.assert()
+            .failure()
+            .stdout("")
+            .stderr(predicate::str::contains("Failed to format some files"));
+    }
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(["--check", "-s"]).write_stdin(UNPARSABLE_FILE);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Failed to parse source"));
+
+    Ok(())
+}
+
+#[test]
+fn unparsable_stdin_reported() -> Result<()> {
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(["-s", "--stdin-filepath", "src/lib.rs"])
+        .write_stdin(UNPARSABLE_FILE);
+
+    cmd.assert()
+        .failure()
+        .stdout("")
+        .stderr(predicate::str::contains(
+            "src/lib.rs: Failed to parse source",
+        ));
+
+    Ok(())
+}
+
+fn count_files(dir: &std::path::Path) -> usize {
+    std::fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .map(|entry| {
+                    let path = entry.unwrap().path();
+                    if path.is_dir() { count_files(&path) } else { 1 }
+                })
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+#[test]
+fn cache_formatted_files() -> Result<()> {
+    // Given
+    let cache_dir = assert_fs::TempDir::new()?;
+    let file = assert_fs::NamedTempFile::new("sample.rs")?;
+    file.write_str(IN_FILE)?;
+
+    // When
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--cache-dir")
+        .arg(cache_dir.path())
+        .arg(file.path());
+
+    // Then
+    cmd.assert().success();
+    file.assert(OUT_FILE);
+    assert_eq!(count_files(cache_dir.path()), 1);
+
+    // When formatted again, with other options
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--cache-dir")
+        .arg(cache_dir.path())
+        .arg(file.path());
+    cmd.assert().success();
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(["--line-length", "20", "--cache-dir"])
+        .arg(cache_dir.path())
+        .arg(file.path());
+    cmd.assert().success();
+
+    // Then
+    assert_eq!(count_files(cache_dir.path()), 2);
+
+    Ok(())
+}
+
+#[test]
+fn cache_skips_files_with_diagnostics() -> Result<()> {
+    // Given
+    let cache_dir = assert_fs::TempDir::new()?;
+    let file = assert_fs::NamedTempFile::new("bad.rs")?;
+    file.write_str("fn bad() {\n    html! { @if { } }\n}\n")?;
+
+    for _ in 0..2 {
+        // When
+        let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+        cmd.arg("--cache-dir")
+            .arg(cache_dir.path())
+            .arg(file.path());
+
+        // Then the diagnostic is reported on every run
+        cmd.assert().success().stderr(predicate::str::contains(
+            "bad.rs:2:5: Failed to parse maud macro",
+        ));
+    }
+    assert_eq!(count_files(cache_dir.path()), 0);
+
+    Ok(())
+}
+
+#[test]
+fn cache_in_target_dir() -> Result<()> {
+    // Given
+    let project = assert_fs::TempDir::new()?;
+    project
+        .child("Cargo.toml")
+        .write_str("[package]\nname = \"sample\"\n")?;
+    let file = project.child("src/lib.rs");
+    file.write_str(IN_FILE)?;
+
+    // When
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.current_dir(project.path())
+        .env_remove("CARGO_TARGET_DIR")
+        .args(["--no-cache", "src"]);
+
+    // Then
+    cmd.assert().success();
+    file.assert(OUT_FILE);
+    project.child("target").assert(predicate::path::missing());
+
+    // When
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.current_dir(project.path())
+        .env_remove("CARGO_TARGET_DIR")
+        .arg("src");
+
+    // Then
+    cmd.assert().success();
+    assert_eq!(count_files(&project.child("target/maudfmt")), 1);
+
+    Ok(())
+}
+
+#[test]
+fn stdin_filepath_selects_input_mode() -> Result<()> {
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(["-s", "--stdin-filepath", "docs/README.md"])
+        .write_stdin(MARKDOWN_IN_FILE);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::diff(MARKDOWN_OUT_FILE));
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(["-s", "--stdin-filepath", "templates/nav.maud"])
+        .write_stdin(FRAGMENT_IN_FILE);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::diff(FRAGMENT_OUT_FILE));
+
+    Ok(())
+}
+
+#[test]
+fn stdin_filepath_labels_diagnostics() -> Result<()> {
+    let source = "fn main() {\n    html! { @if { } }\n}\n";
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(["-s", "--stdin-filepath", "src/lib.rs"])
+        .write_stdin(source);
+
+    cmd.assert()
+        .success()
+        .stdout(source)
+        .stderr(predicate::str::contains(
+            "src/lib.rs:2:5: Failed to parse maud macro",
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn files_from_list() -> Result<()> {
+    // Given
+    let directory = assert_fs::TempDir::new()?;
+    let listed = directory.child("listed.rs");
+    listed.write_str(IN_FILE)?;
+    let not_listed = directory.child("not_listed.rs");
+    not_listed.write_str(IN_FILE)?;
+    let list = directory.child("files.txt");
+    list.write_str(&format!("{}\n\n", listed.path().display()))?;
+
+    // When
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--no-cache").arg("--files-from").arg(list.path());
+
+    // Then
+    cmd.assert().success();
+    listed.assert(OUT_FILE);
+    not_listed.assert(IN_FILE);
+
+    Ok(())
+}
+
+#[test]
+fn files_from_null_separated_stdin() -> Result<()> {
+    // Given
+    let directory = assert_fs::TempDir::new()?;
+    let file_1 = directory.child("with space.rs");
+    file_1.write_str(IN_FILE)?;
+    let file_2 = directory.child("line\nbreak.rs");
+    file_2.write_str(IN_FILE)?;
+
+    // When
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(["--no-cache", "--files-from", "-", "-0"])
+        .write_stdin(format!(
+            "{}\0{}\0",
+            file_1.path().display(),
+            file_2.path().display()
+        ));
+
+    // Then
+    cmd.assert().success();
+    file_1.assert(OUT_FILE);
+    file_2.assert(OUT_FILE);
+
+    Ok(())
+}
+
+#[test]
+fn files_from_paths_are_not_globs() -> Result<()> {
+    // Given
+    let directory = assert_fs::TempDir::new()?;
+    let routes = directory.child("routes/[id] page");
+    let file = routes.child("view.rs");
+    file.write_str(IN_FILE)?;
+    let glob_lookalike = directory.child("*.rs");
+    glob_lookalike.write_str(IN_FILE)?;
+    let other = directory.child("other.rs");
+    other.write_str(IN_FILE)?;
+
+    // When
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(["--no-cache", "--files-from", "-"])
+        .write_stdin(format!(
+            "{}\n{}\n",
+            routes.path().display(),
+            glob_lookalike.path().display()
+        ));
+
+    // Then
+    cmd.assert().success();
+    file.assert(OUT_FILE);
+    glob_lookalike.assert(OUT_FILE);
+    other.assert(IN_FILE);
+
+    Ok(())
+}
+
+#[test]
+fn format_dir_with_special_characters() -> Result<()> {
+    // Given
+    let directory = assert_fs::TempDir::new()?;
+    let routes = directory.child("routes/[id] page");
+    let file = routes.child("view.rs");
+    file.write_str(IN_FILE)?;
+
+    // When
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--no-cache").arg(routes.path());
+
+    // Then
+    cmd.assert().success();
+    file.assert(OUT_FILE);
+
+    Ok(())
+}
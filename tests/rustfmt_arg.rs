@@ -76,7 +76,12 @@ fn execute_rustfmt_on_stdin() -> Result<()> {
 
     // When
     let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
-    cmd.arg("--rustfmt").arg("-s").pipe_stdin(file)?;
+    // outside of this crate, so that rustfmt doesn't pick the 2024 edition
+    let dir = assert_fs::TempDir::new()?;
+    cmd.current_dir(dir.path())
+        .arg("--rustfmt")
+        .arg("-s")
+        .pipe_stdin(file)?;
 
     // Then
     cmd.assert()
@@ -85,3 +90,476 @@ fn execute_rustfmt_on_stdin() -> Result<()> {
 
     Ok(())
 }
+
+/// Fake rustfmt recording its arguments and input next to itself, then running `body`
+#[cfg(unix)]
+fn stub_rustfmt(dir: &assert_fs::TempDir, body: &str) -> Result<std::path::PathBuf> {
+    use std::os::unix::fs::PermissionsExt as _;
+
+    let stub = dir.child("rustfmt");
+    stub.write_str(&format!(
+        "#!/bin/sh\necho \"$@\" > \"{dir}/args\"\ntee \"{dir}/input\"{body}\n",
+        dir = dir.path().display(),
+    ))?;
+    std::fs::set_permissions(stub.path(), std::fs::Permissions::from_mode(0o755))?;
+    Ok(stub.path().to_path_buf())
+}
+
+#[cfg(unix)]
+#[test]
+fn rustfmt_edition_from_cargo_toml() -> Result<()> {
+    // Given
+    let dir = assert_fs::TempDir::new()?;
+    let stub = stub_rustfmt(&dir, "")?;
+    dir.child("crate/Cargo.toml")
+        .write_str("[package]\nname = \"sample\"\nedition = \"2021\"\n")?;
+    let file = dir.child("crate/src/lib.rs");
+    file.write_str("fn main() {}\n")?;
+
+    // When
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.env("RUSTFMT", &stub).arg("--rustfmt").arg(file.path());
+
+    // Then
+    cmd.assert().success();
+    dir.child("args").assert("--edition 2021\n");
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn rustfmt_edition_with_comment() -> Result<()> {
+    // Given
+    let dir = assert_fs::TempDir::new()?;
+    let stub = stub_rustfmt(&dir, "")?;
+    dir.child("crate/Cargo.toml").write_str(
+        "[package] # sample\nname = \"sample\"\nedition = \"2021\" # like the others\n",
+    )?;
+    let file = dir.child("crate/src/lib.rs");
+    file.write_str("fn main() {}\n")?;
+
+    // When
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.env("RUSTFMT", &stub).arg("--rustfmt").arg(file.path());
+
+    // Then
+    cmd.assert().success();
+    dir.child("args").assert("--edition 2021\n");
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn rustfmt_edition_from_workspace() -> Result<()> {
+    // Given
+    let dir = assert_fs::TempDir::new()?;
+    let stub = stub_rustfmt(&dir, "")?;
+    dir.child("Cargo.toml").write_str(
+        "[workspace]\nmembers = [\"crate\"]\n\n[workspace.package]\nedition = \"2024\"\n",
+    )?;
+    dir.child("crate/Cargo.toml")
+        .write_str("[package]\nname = \"sample\"\nedition.workspace = true\n")?;
+    let file = dir.child("crate/src/lib.rs");
+    file.write_str("fn main() {}\n")?;
+
+    // When
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.env("RUSTFMT", &stub).arg("--rustfmt").arg(file.path());
+
+    // Then
+    cmd.assert().success();
+    dir.child("args").assert("--edition 2024\n");
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn rustfmt_edition_and_config_path_flags() -> Result<()> {
+    // Given
+    let dir = assert_fs::TempDir::new()?;
+    let stub = stub_rustfmt(&dir, "")?;
+
+    // When
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.env("RUSTFMT", &stub)
+        .current_dir(dir.path())
+        .args([
+            "--rustfmt",
+            "--edition",
+            "2018",
+            "--config-path",
+            "rustfmt.toml",
+            "-s",
+        ])
+        .write_stdin("fn main() {}\n");
+
+    // Then
+    cmd.assert().success().stdout("fn main() {}\n");
+    dir.child("args")
+        .assert("--edition 2018 --config-path rustfmt.toml\n");
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn rustfmt_before_and_after_maudfmt() -> Result<()> {
+    let source = "fn main() { html!{p{\"x\"}} }\n";
+
+    for (stage, expected_input) in [
+        ("after", "fn main() { html! {\n    p { \"x\" }\n} }\n"),
+        ("before", source),
+    ] {
+        // Given
+        let dir = assert_fs::TempDir::new()?;
+        let stub = stub_rustfmt(&dir, "")?;
+
+        // When
+        let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+        cmd.env("RUSTFMT", &stub)
+            .current_dir(dir.path())
+            .arg(format!("--rustfmt={stage}"))
+            .arg("-s")
+            .write_stdin(source);
+
+        // Then
+        cmd.assert().success();
+        dir.child("input").assert(expected_input);
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn rustfmt_failure_is_reported() -> Result<()> {
+    // Given
+    let dir = assert_fs::TempDir::new()?;
+    let stub = stub_rustfmt(
+        &dir,
+        " > /dev/null\necho 'error: expected item, found `}`' >&2\nexit 1",
+    )?;
+    let file = dir.child("broken.rs");
+    file.write_str("fn main() { html!{p{}} }\n")?;
+    let other_file = dir.child("other.rs");
+    other_file.write_str("fn other() {}\n")?;
+
+    // When
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.env("RUSTFMT", &stub)
+        .arg("--rustfmt")
+        .arg(file.path())
+        .arg(other_file.path());
+
+    // Then
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("broken.rs: rustfmt: failed"))
+        .stderr(predicate::str::contains("error: expected item, found `}`"))
+        .stderr(predicate::str::contains("other.rs: rustfmt: failed"));
+    file.assert("fn main() { html!{p{}} }\n");
+
+    Ok(())
+}
+
+#[test]
+fn rustfmt_not_found_is_reported() -> Result<()> {
+    // When
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.env("RUSTFMT", "/nonexistent/rustfmt")
+        .args(["--rustfmt", "-s"])
+        .write_stdin("fn main() {}\n");
+
+    // Then
+    cmd.assert()
+        .failure()
+        .stdout("")
+        .stderr(predicate::str::contains(
+            "rustfmt: failed to run `/nonexistent/rustfmt`",
+        ));
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn rustfmt_wrapper_formats_stdin() -> Result<()> {
+    // Given
+    let dir = assert_fs::TempDir::new()?;
+    let stub = stub_rustfmt(&dir, "")?;
+    let source = "fn main() { html!{p{\"x\"}} }\n";
+
+    // When
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.env("RUSTFMT", &stub)
+        .current_dir(dir.path())
+        .args([
+            "rustfmt-wrapper",
+            "--line-length",
+            "80",
+            "--edition",
+            "2021",
+            "--emit",
+            "stdout",
+        ])
+        .write_stdin(source);
+
+    // Then
+    cmd.assert()
+        .success()
+        .stdout("fn main() { html! {\n    p { \"x\" }\n} }\n");
+    // rustfmt runs first, on the original source
+    dir.child("args").assert("--edition 2021 --emit stdout\n");
+    dir.child("input").assert(source);
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn rustfmt_failure_without_reading_input_is_reported() -> Result<()> {
+    use std::os::unix::fs::PermissionsExt as _;
+
+    // Given a rustfmt failing before reading an input larger than the pipe buffer
+    let dir = assert_fs::TempDir::new()?;
+    let stub = dir.child("rustfmt");
+    stub.write_str("#!/bin/sh\necho 'error: input too large' >&2\nexit 1\n")?;
+    std::fs::set_permissions(stub.path(), std::fs::Permissions::from_mode(0o755))?;
+
+    // When
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.env("RUSTFMT", stub.path())
+        .current_dir(dir.path())
+        .args(["--rustfmt=before", "-s"])
+        .write_stdin("fn main() {}\n".repeat(100_000));
+
+    // Then
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("rustfmt: failed"))
+        .stderr(predicate::str::contains("error: input too large"));
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn rustfmt_wrapper_behaves_like_failing_rustfmt() -> Result<()> {
+    // Given
+    let dir = assert_fs::TempDir::new()?;
+    let stub = stub_rustfmt(
+        &dir,
+        " > /dev/null\necho 'error: expected item, found `}`' >&2\nexit 3",
+    )?;
+
+    // When
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.env("RUSTFMT", &stub)
+        .current_dir(dir.path())
+        .args(["rustfmt-wrapper", "--edition", "2021"])
+        .write_stdin("fn main() { html!{p{}} \n");
+
+    // Then
+    cmd.assert()
+        .code(3)
+        .stdout("")
+        .stderr("error: expected item, found `}`\n");
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn rustfmt_wrapper_check_is_passed_through() -> Result<()> {
+    // Given
+    let dir = assert_fs::TempDir::new()?;
+    let stub = stub_rustfmt(&dir, " > /dev/null\necho 'Diff in stdin'\nexit 1")?;
+
+    // When
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.env("RUSTFMT", &stub)
+        .current_dir(dir.path())
+        .args(["rustfmt-wrapper", "--check"])
+        .write_stdin("fn main() { html!{p{}} }\n");
+
+    // Then
+    cmd.assert().code(1).stdout("Diff in stdin\n");
+    dir.child("args").assert("--check\n");
+
+    Ok(())
+}
+
+#[test]
+fn rustfmt_wrapper_with_rustfmt() -> Result<()> {
+    // When
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(["rustfmt-wrapper", "--edition", "2021"])
+        .write_stdin(IN_FILE);
+
+    // Then
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::diff(OUT_FILE));
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn rustfmt_edition_from_stdin_filepath() -> Result<()> {
+    // Given
+    let dir = assert_fs::TempDir::new()?;
+    let stub = stub_rustfmt(&dir, "")?;
+    dir.child("crate/Cargo.toml")
+        .write_str("[package]\nname = \"sample\"\nedition = \"2018\"\n")?;
+    dir.child("crate/src/lib.rs").write_str("fn main() {}\n")?;
+
+    // When
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.env("RUSTFMT", &stub)
+        .current_dir(dir.path())
+        .args(["--rustfmt", "-s", "--stdin-filepath"])
+        .arg(dir.child("crate/src/lib.rs").path())
+        .write_stdin("fn main() {}\n");
+
+    // Then
+    cmd.assert().success().stdout("fn main() {}\n");
+    dir.child("args").assert("--edition 2018\n");
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn rustfmt_failure_labeled_with_stdin_filepath() -> Result<()> {
+    // Given
+    let dir = assert_fs::TempDir::new()?;
+    let stub = stub_rustfmt(
+        &dir,
+        " > /dev/null\necho 'error: expected item' >&2\nexit 1",
+    )?;
+
+    // When
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.env("RUSTFMT", &stub)
+        .current_dir(dir.path())
+        .args(["--rustfmt", "-s", "--stdin-filepath", "src/main.rs"])
+        .write_stdin("fn main( {}\n");
+
+    // Then
+    cmd.assert()
+        .failure()
+        .stdout("")
+        .stderr(predicate::str::contains("src/main.rs: rustfmt: failed"))
+        .stderr(predicate::str::contains("error: expected item"));
+
+    Ok(())
+}
+
+#[test]
+fn rustfmt_formats_embedded_rust_code() -> Result<()> {
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(["-s", "--rust-formatter", "rustfmt"])
+        .write_stdin("html! { @if let Some(Foo{a,b}) = foo { (a+b) } }\n");
+
+    cmd.assert()
+        .success()
+        .stdout("html! {\n    @if let Some(Foo { a, b }) = foo { (a + b) }\n}\n");
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn rust_formatter_rustfmt_batches_and_uses_options() -> Result<()> {
+    use std::os::unix::fs::PermissionsExt as _;
+
+    // Given a rustfmt recording its calls
+    let dir = assert_fs::TempDir::new()?;
+    let stub = dir.child("rustfmt");
+    stub.write_str(&format!(
+        "#!/bin/sh\necho \"$@\" >> \"{}/calls\"\nexec rustfmt \"$@\"\n",
+        dir.path().display(),
+    ))?;
+    std::fs::set_permissions(stub.path(), std::fs::Permissions::from_mode(0o755))?;
+    dir.child("crate/Cargo.toml")
+        .write_str("[package]\nname = \"sample\"\nedition = \"2021\"\n")?;
+    let config = dir.child("rustfmt.toml");
+    config.write_str("")?;
+    let file = dir.child("crate/src/lib.rs");
+    file.write_str("fn page() -> Markup {\n    html! { (a+b) (c*d) @if x==1 { (e-f) } }\n}\n")?;
+
+    // When
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.env("RUSTFMT", stub.path())
+        .args(["--no-cache", "--rust-formatter", "rustfmt", "--config-path"])
+        .arg(config.path())
+        .arg(file.path());
+
+    // Then the four snippets are formatted together, with the edition and configuration
+    cmd.assert().success();
+    file.assert(
+        "fn page() -> Markup {\n    html! {\n        (a + b)\n        (c * d)\n        @if x == 1 { (e - f) }\n    }\n}\n",
+    );
+    let calls = std::fs::read_to_string(dir.child("calls").path())?;
+    assert_eq!(calls.lines().count(), 1, "{calls}");
+    assert!(calls.starts_with(&format!(
+        "--edition 2021 --config-path {} --config max_width=",
+        config.path().display()
+    )));
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn rust_formatter_rustfmt_failure_reported() -> Result<()> {
+    // Given
+    let dir = assert_fs::TempDir::new()?;
+    let stub = stub_rustfmt(&dir, " > /dev/null\necho 'error: unexpected' >&2\nexit 1")?;
+
+    // When
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.env("RUSTFMT", &stub)
+        .current_dir(dir.path())
+        .args(["-s", "--stdin-filepath", "src/lib.rs"])
+        .args(["--rust-formatter", "rustfmt"])
+        .write_stdin("fn page() -> Markup {\n    html!{ p{(a+b)} }\n}\n");
+
+    // Then the code is kept as written and reported once
+    cmd.assert()
+        .success()
+        .stdout("fn page() -> Markup {\n    html! {\n        p { (a+b) }\n    }\n}\n")
+        .stderr(predicate::str::contains("src/lib.rs:2:15: failed to format rust code").count(1));
+
+    Ok(())
+}
+
+#[test]
+fn rust_formatter_rustfmt_keeps_the_indentation() -> Result<()> {
+    // Given a configuration with another indentation
+    let dir = assert_fs::TempDir::new()?;
+    let config = dir.child("rustfmt.toml");
+    config.write_str("tab_spaces = 2\nhard_tabs = true\n")?;
+    let file = dir.child("lib.rs");
+    file.write_str(
+        "fn page() -> Markup {\n    html! { @if x { (match value { Some(value) => value.to_string(), None => String::from(\"nothing\") }) } }\n}\n",
+    )?;
+
+    // When
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(["--no-cache", "--rust-formatter", "rustfmt", "--config-path"])
+        .arg(config.path())
+        .arg(file.path());
+
+    // Then the code is indented like the markup
+    cmd.assert().success();
+    file.assert(
+        "fn page() -> Markup {\n    html! {\n        @if x {\n            ({\n                match value {\n                    Some(value) => value.to_string(),\n                    None => String::from(\"nothing\"),\n                }\n            })\n        }\n    }\n}\n",
+    );
+
+    Ok(())
+}
@@ -0,0 +1,141 @@
+use std::{
+    io::{BufRead as _, BufReader, Read},
+    process::{Child, Command, Stdio},
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::Duration,
+};
+
+use anyhow::Result;
+use assert_fs::prelude::*;
+use pretty_assertions::assert_eq;
+
+static IN_FILE: &str = "fn footer() -> Markup {\n    html!{footer{\"RSS Feed\"}}\n}\n";
+
+static OUT_FILE: &str =
+    "fn footer() -> Markup {\n    html! {\n        footer { \"RSS Feed\" }\n    }\n}\n";
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Kills the watcher when the test ends, even on failure
+struct Watcher {
+    child: Child,
+    stdout: Receiver<String>,
+    stderr: Receiver<String>,
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn lines(stream: impl Read + Send + 'static) -> Receiver<String> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for line in BufReader::new(stream).lines().map_while(Result::ok) {
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+fn wait_for_line(lines: &Receiver<String>, pattern: &str) -> String {
+    loop {
+        let line = lines
+            .recv_timeout(TIMEOUT)
+            .unwrap_or_else(|_| panic!("no line containing `{pattern}`"));
+        if line.contains(pattern) {
+            return line;
+        }
+    }
+}
+
+fn watch(dir: &assert_fs::TempDir) -> Result<Watcher> {
+    let mut child = Command::new(assert_cmd::cargo::cargo_bin!("maudfmt"))
+        .arg("--watch")
+        .arg(dir.path())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let stdout = lines(child.stdout.take().expect("stdout is piped"));
+    let stderr = lines(child.stderr.take().expect("stderr is piped"));
+
+    let watcher = Watcher {
+        child,
+        stdout,
+        stderr,
+    };
+    wait_for_line(&watcher.stdout, "Watching for changes");
+    Ok(watcher)
+}
+
+#[test]
+fn watch_formats_saved_files() -> Result<()> {
+    // Given
+    let dir = assert_fs::TempDir::new()?;
+    dir.child("src").create_dir_all()?;
+    let mut watcher = watch(&dir)?;
+
+    // When
+    let file = dir.child("src/footer.rs");
+    file.write_str(IN_FILE)?;
+
+    // Then
+    wait_for_line(&watcher.stdout, "footer.rs");
+    assert_eq!(std::fs::read_to_string(&file)?, OUT_FILE);
+
+    // its own write doesn't trigger a new format
+    assert!(watcher.stdout.recv_timeout(Duration::from_secs(1)).is_err());
+    assert!(watcher.child.try_wait()?.is_none());
+
+    Ok(())
+}
+
+#[test]
+fn watch_only_selected_files() -> Result<()> {
+    // Given
+    let dir = assert_fs::TempDir::new()?;
+    let watcher = watch(&dir)?;
+
+    // When
+    let ignored = dir.child("notes.txt");
+    ignored.write_str(IN_FILE)?;
+    let file = dir.child("footer.rs");
+    file.write_str(IN_FILE)?;
+
+    // Then
+    let formatted = wait_for_line(&watcher.stdout, "Formatted");
+    assert!(formatted.ends_with("footer.rs"), "{formatted}");
+    ignored.assert(IN_FILE);
+
+    Ok(())
+}
+
+#[test]
+fn watch_reports_errors_and_keeps_running() -> Result<()> {
+    // Given
+    let dir = assert_fs::TempDir::new()?;
+    let mut watcher = watch(&dir)?;
+
+    // When
+    let invalid = dir.child("invalid.rs");
+    invalid.write_binary(&[0xff, 0xfe, 0x00])?;
+
+    // Then
+    wait_for_line(&watcher.stderr, "invalid.rs");
+    assert!(watcher.child.try_wait()?.is_none());
+
+    // When
+    let file = dir.child("footer.rs");
+    file.write_str(IN_FILE)?;
+
+    // Then
+    wait_for_line(&watcher.stdout, "footer.rs");
+    file.assert(OUT_FILE);
+
+    Ok(())
+}